@@ -1,84 +1,128 @@
 #include "sherpa.h"
 
 #include <stdio.h>
+#include <stdlib.h>
 #include <string.h>
 
-#define SAMPLE_RATE 16000
 
-SherpaHandle sherpa_init(const char* tokens, const char* encoder,
-    const char* decoder, const char* joiner) {
-    SherpaHandle handle;
-    //初始化SherpaHandle
-    memset(&handle, 0, sizeof(handle));
-    if (strcmp("", tokens) == 0
-      || strcmp("", encoder) == 0
-      || strcmp("", decoder) == 0
-      || strcmp("", joiner) == 0) {
-        return handle;
+const SherpaOnnxOnlineRecognizer* sherpa_create_recognizer(const SherpaModelConfig* model_config) {
+    if (model_config == NULL
+      || model_config->tokens == NULL || strcmp("", model_config->tokens) == 0
+      || model_config->encoder == NULL || strcmp("", model_config->encoder) == 0
+      || model_config->decoder == NULL || strcmp("", model_config->decoder) == 0
+      || model_config->joiner == NULL || strcmp("", model_config->joiner) == 0) {
+        return NULL;
     }
     SherpaOnnxOnlineRecognizerConfig config;
     memset(&config, 0, sizeof(config));
 
     config.model_config.debug = 0;
-    config.model_config.num_threads = 1;
-    config.model_config.provider = "cpu";
+    config.model_config.num_threads = model_config->num_threads;
+    config.model_config.provider = model_config->provider;
 
-    config.decoding_method = "greedy_search";
+    config.decoding_method = model_config->decoding_method;
 
-    config.max_active_paths = 4;
+    config.max_active_paths = model_config->max_active_paths;
 
-    config.feat_config.sample_rate = 16000;
-    config.feat_config.feature_dim = 80;
+    config.feat_config.sample_rate = model_config->sample_rate;
+    config.feat_config.feature_dim = model_config->feature_dim;
 
-    config.enable_endpoint = 1;
-    config.rule1_min_trailing_silence = 2.4;
-    config.rule2_min_trailing_silence = 1.2;
-    config.rule3_min_utterance_length = 300;
+    config.enable_endpoint = model_config->enable_endpoint;
+    config.rule1_min_trailing_silence = model_config->rule1_min_trailing_silence;
+    config.rule2_min_trailing_silence = model_config->rule2_min_trailing_silence;
+    config.rule3_min_utterance_length = model_config->rule3_min_utterance_length;
 
-    config.model_config.tokens = tokens;
-    config.model_config.transducer.encoder = encoder;
-    config.model_config.transducer.decoder = decoder;
-    config.model_config.transducer.joiner = joiner;
+    config.model_config.tokens = model_config->tokens;
+    config.model_config.transducer.encoder = model_config->encoder;
+    config.model_config.transducer.decoder = model_config->decoder;
+    config.model_config.transducer.joiner = model_config->joiner;
 
-    handle.recognizer = SherpaOnnxCreateOnlineRecognizer(&config);  
-    handle.stream = SherpaOnnxCreateOnlineStream(handle.recognizer);  
-    return handle;
+    return SherpaOnnxCreateOnlineRecognizer(&config);
 }
 
-void sherpa_transcribe(SherpaHandle handle, char* result, float* samples, int len) {
-    SherpaOnnxOnlineStreamAcceptWaveform(handle.stream, SAMPLE_RATE, samples, len);
-    while (SherpaOnnxIsOnlineStreamReady(handle.recognizer, handle.stream)) {
-      SherpaOnnxDecodeOnlineStream(handle.recognizer, handle.stream);
+void sherpa_destroy_recognizer(const SherpaOnnxOnlineRecognizer* recognizer) {
+    if (recognizer != NULL) {
+        SherpaOnnxDestroyOnlineRecognizer(recognizer);
     }
+}
 
-    const SherpaOnnxOnlineRecognizerResult *r =
-        SherpaOnnxGetOnlineStreamResult(handle.recognizer, handle.stream);
-    
-    memset(result, 0, MAX_SUPPORT_TOKENS);
-    if (strlen(r->text)) {
-      strcat(result, r->text);
-    }
-    
-    if (SherpaOnnxOnlineStreamIsEndpoint(handle.recognizer, handle.stream)) {
-      SherpaOnnxOnlineStreamReset(handle.recognizer, handle.stream);
+SherpaHandle sherpa_create_stream(const SherpaOnnxOnlineRecognizer* recognizer, int32_t sample_rate) {
+    SherpaHandle handle;
+    //初始化SherpaHandle
+    memset(&handle, 0, sizeof(handle));
+    if (recognizer == NULL) {
+        return handle;
     }
+    handle.recognizer = recognizer;
+    handle.stream = SherpaOnnxCreateOnlineStream(recognizer);
+    handle.sample_rate = sample_rate;
+    return handle;
+}
 
-    SherpaOnnxDestroyOnlineRecognizerResult(r);
+void sherpa_accept_waveform(SherpaHandle handle, const float* samples, int len) {
+    SherpaOnnxOnlineStreamAcceptWaveform(handle.stream, handle.sample_rate, samples, len);
 }
 
-void sherpa_reset(SherpaHandle handle) {
+void sherpa_input_finished(SherpaHandle handle) {
     float tail_paddings[4800] = { 0 };
-    SherpaOnnxOnlineStreamAcceptWaveform(handle.stream, SAMPLE_RATE, tail_paddings, 4800);
+    SherpaOnnxOnlineStreamAcceptWaveform(handle.stream, handle.sample_rate, tail_paddings, 4800);
     SherpaOnnxOnlineStreamInputFinished(handle.stream);
-    while (SherpaOnnxIsOnlineStreamReady(handle.recognizer, handle.stream)) {
-        SherpaOnnxDecodeOnlineStream(handle.recognizer, handle.stream);
+}
+
+int sherpa_is_ready(SherpaHandle handle) {
+    return SherpaOnnxIsOnlineStreamReady(handle.recognizer, handle.stream);
+}
+
+void sherpa_decode_streams(const SherpaOnnxOnlineRecognizer* recognizer, const SherpaHandle* handles, int n) {
+    if (n <= 0) {
+        return;
+    }
+    if (n == 1) {
+        SherpaOnnxDecodeOnlineStream(recognizer, handles[0].stream);
+        return;
+    }
+    const SherpaOnnxOnlineStream** streams = malloc(sizeof(SherpaOnnxOnlineStream*) * n);
+    if (streams == NULL) {
+        return;
+    }
+    for (int i = 0; i < n; i++) {
+        streams[i] = handles[i].stream;
+    }
+    SherpaOnnxDecodeMultipleOnlineStreams(recognizer, streams, n);
+    free(streams);
+}
+
+SherpaResult sherpa_get_result(SherpaHandle handle) {
+    SherpaResult result;
+    memset(&result, 0, sizeof(result));
+
+    const SherpaOnnxOnlineRecognizerResult *r =
+        SherpaOnnxGetOnlineStreamResult(handle.recognizer, handle.stream);
+    if (r == NULL) {
+      return result;
     }
+    result.text = r->text;
+    result.tokens = r->tokens_arr;
+    result.timestamps = r->timestamps;
+    result.count = r->count;
+    result.impl = r;
+    result.is_endpoint = SherpaOnnxOnlineStreamIsEndpoint(handle.recognizer, handle.stream) ? 1 : 0;
+    return result;
+}
+
+void sherpa_destroy_result(SherpaResult result) {
+    if (result.impl != NULL) {
+      SherpaOnnxDestroyOnlineRecognizerResult(result.impl);
+    }
+}
+
+void sherpa_reset_stream(SherpaHandle handle) {
     SherpaOnnxOnlineStreamReset(handle.recognizer, handle.stream);
 }
 
 void sherpa_close(SherpaHandle handle) {
     float tail_paddings[4800] = { 0 };
-    SherpaOnnxOnlineStreamAcceptWaveform(handle.stream, SAMPLE_RATE, tail_paddings, 4800);
+    SherpaOnnxOnlineStreamAcceptWaveform(handle.stream, handle.sample_rate, tail_paddings, 4800);
     SherpaOnnxOnlineStreamInputFinished(handle.stream);
     while (SherpaOnnxIsOnlineStreamReady(handle.recognizer, handle.stream)) {
         SherpaOnnxDecodeOnlineStream(handle.recognizer, handle.stream);
@@ -89,5 +133,4 @@ void sherpa_close(SherpaHandle handle) {
     SherpaOnnxDestroyOnlineRecognizerResult(r);
 
     SherpaOnnxDestroyOnlineStream(handle.stream);
-    SherpaOnnxDestroyOnlineRecognizer(handle.recognizer);
 }
\ No newline at end of file
@@ -7,16 +7,58 @@
 
 #include <sherpa-onnx/c-api/c-api.h>
 
-#define MAX_SUPPORT_TOKENS  2048
+typedef struct {
+    const char *tokens;
+    const char *encoder;
+    const char *decoder;
+    const char *joiner;
+    int32_t num_threads;
+    const char *provider;
+    const char *decoding_method;
+    int32_t max_active_paths;
+    int32_t sample_rate;
+    int32_t feature_dim;
+    int32_t enable_endpoint;
+    float rule1_min_trailing_silence;
+    float rule2_min_trailing_silence;
+    float rule3_min_utterance_length;
+} SherpaModelConfig;
 
 typedef struct {
     const SherpaOnnxOnlineRecognizer *recognizer;
     const SherpaOnnxOnlineStream *stream;
+    // 送入 stream 的采样率，与模型配置的 sample_rate 一致
+    int32_t sample_rate;
 } SherpaHandle;
 
-SherpaHandle sherpa_init(const char* tokens, const char* encoder,
-    const char* decoder, const char* joiner);
-void sherpa_transcribe(SherpaHandle handle, char* result, float* samples, int len);
+// 识别结果，内存由 sherpa-onnx 分配，使用完后需要调用 sherpa_destroy_result 释放
+typedef struct {
+    const char *text;
+    // count 个 token 字符串
+    const char *const *tokens;
+    // count 个 token 时间戳（秒），模型不支持时为 NULL
+    const float *timestamps;
+    int32_t count;
+    // 检测到端点，本句已结束，调用方需要调用 sherpa_reset_stream 开始下一句
+    int32_t is_endpoint;
+    const SherpaOnnxOnlineRecognizerResult *impl;
+} SherpaResult;
+
+// recognizer 持有模型，可以被多个 stream 共享；stream 只保存单个会话的解码状态
+const SherpaOnnxOnlineRecognizer* sherpa_create_recognizer(const SherpaModelConfig* model_config);
+void sherpa_destroy_recognizer(const SherpaOnnxOnlineRecognizer* recognizer);
+SherpaHandle sherpa_create_stream(const SherpaOnnxOnlineRecognizer* recognizer, int32_t sample_rate);
+// 送数据、解码、取结果分成几步，便于多个 stream 批量解码
+void sherpa_accept_waveform(SherpaHandle handle, const float* samples, int len);
+void sherpa_input_finished(SherpaHandle handle);
+int sherpa_is_ready(SherpaHandle handle);
+void sherpa_decode_streams(const SherpaOnnxOnlineRecognizer* recognizer, const SherpaHandle* handles, int n);
+// 只读取结果，不改变 stream 的解码状态
+SherpaResult sherpa_get_result(SherpaHandle handle);
+void sherpa_destroy_result(SherpaResult result);
+// 清空 stream 的解码状态，检测到端点后用来开始下一句；sherpa_input_finished 之后 stream 不能再送数据，只能关闭
+void sherpa_reset_stream(SherpaHandle handle);
+// 只销毁 stream，recognizer 需要在所有 stream 关闭后调用 sherpa_destroy_recognizer 销毁
 void sherpa_close(SherpaHandle handle);
 
 #endif /* SHERPA_H_ */
\ No newline at end of file
@@ -9,7 +9,24 @@ const char* SHERPA_JOINER = "../sherpa-models/joiner-epoch-20-avg-1-chunk-16-lef
 
 int main() {
     const char* pcm_file = "test.pcm";
-    SherpaHandle handler = sherpa_init(SHERPA_TOKENS, SHERPA_ENCODER, SHERPA_DECODER, SHERPA_JOINER);
+    SherpaModelConfig model_config = {
+        .tokens = SHERPA_TOKENS,
+        .encoder = SHERPA_ENCODER,
+        .decoder = SHERPA_DECODER,
+        .joiner = SHERPA_JOINER,
+        .num_threads = 1,
+        .provider = "cpu",
+        .decoding_method = "greedy_search",
+        .max_active_paths = 4,
+        .sample_rate = 16000,
+        .feature_dim = 80,
+        .enable_endpoint = 1,
+        .rule1_min_trailing_silence = 2.4,
+        .rule2_min_trailing_silence = 1.2,
+        .rule3_min_utterance_length = 300,
+    };
+    const SherpaOnnxOnlineRecognizer* recognizer = sherpa_create_recognizer(&model_config);
+    SherpaHandle handler = sherpa_create_stream(recognizer, model_config.sample_rate);
 
     FILE* fp = fopen(pcm_file, "rb");
     if (fp == NULL) {
@@ -20,8 +37,6 @@ int main() {
     unsigned char buff[6400];
     int read_len = 0;
 
-    char ret[MAX_SUPPORT_TOKENS];
-
     do {
         read_len = fread(buff, sizeof(unsigned char), 6400, fp);
         printf("read_len: %d\n", read_len);
@@ -37,10 +52,19 @@ int main() {
             sample[k] /= 32767.0;
         }
 
-        sherpa_transcribe(handler, ret, sample, 3200);
-        printf("ret: %s\n", ret);
+        sherpa_accept_waveform(handler, sample, 3200);
+        while (sherpa_is_ready(handler)) {
+            sherpa_decode_streams(recognizer, &handler, 1);
+        }
+        SherpaResult ret = sherpa_get_result(handler);
+        printf("ret: %s%s\n", ret.text == NULL ? "" : ret.text, ret.is_endpoint ? " (endpoint)" : "");
+        if (ret.is_endpoint) {
+            sherpa_reset_stream(handler);
+        }
+        sherpa_destroy_result(ret);
     } while (read_len > 0);
     
     sherpa_close(handler);
+    sherpa_destroy_recognizer(recognizer);
     return 0;
 }
\ No newline at end of file
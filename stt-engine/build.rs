@@ -10,4 +10,10 @@ fn main() {
     println!("cargo:rustc-link-search=native={}/", lib_path);
     println!("cargo:rustc-link-lib=dylib=sherpa-bridge");
     println!("cargo:rustc-link-arg=-Wl,-rpath,{}/", lib_path);
-}
\ No newline at end of file
+
+    // 用纯 Rust 的 protox 编译 proto 文件，构建环境不需要安装 protoc
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/stt.proto");
+    let descriptors = protox::compile(["proto/stt.proto"], ["proto"]).expect("Failed to compile proto/stt.proto");
+    tonic_prost_build::configure().compile_fds(descriptors).expect("Failed to generate gRPC code");
+}
@@ -0,0 +1,650 @@
+use std::str::FromStr;
+
+use rubato::{Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction};
+
+use crate::{codec::{self, MAX_COMPRESSED_SIZE}, sherpa::env_override, wav::{AudioContainer, WavFormat, WavStreamParser, WAVE_FORMAT_ALAW, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_MULAW, WAVE_FORMAT_PCM}};
+
+// 重采样器每次处理的输入采样数
+const RESAMPLER_CHUNK_SIZE: usize = 1024;
+
+// 单个会话最多支持的声道数，分别识别时每个声道占用一个识别流
+pub const MAX_CHANNELS: usize = 8;
+
+/// 会话输入音频的配置，`sample_rate`、`sample_format`、`channels` 描述裸 PCM 输入，
+/// WAV 输入以头部声明的格式为准。
+#[derive(Debug, Clone)]
+pub struct AudioConfig {
+    pub container: AudioContainer,
+    pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+    pub channels: usize,
+    pub channel_mode: ChannelMode,
+    /// 分别识别时各声道的标签，例如 `agent`、`customer`，没有配置的声道使用序号
+    pub channel_labels: Vec<String>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            container: AudioContainer::Raw,
+            sample_rate: 16000,
+            sample_format: SampleFormat::S16Le,
+            channels: 1,
+            channel_mode: ChannelMode::Downmix,
+            channel_labels: Vec::new(),
+        }
+    }
+}
+
+impl AudioConfig {
+    /// 在默认配置的基础上读取 `STT_AUDIO_CONTAINER`、`STT_INPUT_SAMPLE_RATE`、`STT_INPUT_SAMPLE_FORMAT`、
+    /// `STT_INPUT_CHANNELS`、`STT_CHANNEL_MODE`，以及逗号分隔的 `STT_CHANNEL_LABELS`。
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Self::default();
+        env_override("STT_AUDIO_CONTAINER", &mut config.container)?;
+        env_override("STT_INPUT_SAMPLE_RATE", &mut config.sample_rate)?;
+        env_override("STT_INPUT_SAMPLE_FORMAT", &mut config.sample_format)?;
+        env_override("STT_INPUT_CHANNELS", &mut config.channels)?;
+        env_override("STT_CHANNEL_MODE", &mut config.channel_mode)?;
+        if let Ok(labels) = std::env::var("STT_CHANNEL_LABELS") {
+            config.channel_labels = labels.split(',').map(|label| label.trim().to_string()).collect();
+        }
+        if config.sample_rate == 0 {
+            return Err("Invalid value for STT_INPUT_SAMPLE_RATE: 0".to_string());
+        }
+        check_channels(config.channels)?;
+        if let Some(label) = config.channel_labels.iter().find(|label| !is_valid_label(label)) {
+            return Err(format!("Invalid value for STT_CHANNEL_LABELS: {:?}", label));
+        }
+        Ok(config)
+    }
+
+    /// 第 `index` 个识别流输出的声道标签，只有分别识别时才有标签
+    pub fn channel_label(&self, index: usize) -> Option<String> {
+        match self.channel_mode {
+            ChannelMode::Downmix => None,
+            ChannelMode::Separate => Some(self.channel_labels.get(index).cloned().unwrap_or_else(|| index.to_string())),
+        }
+    }
+}
+
+// 标签出现在 `<channel>:<segment>` 字段中，不能为空，也不能包含空白
+pub(crate) fn is_valid_label(label: &str) -> bool {
+    !label.is_empty() && !label.contains(char::is_whitespace)
+}
+
+pub(crate) fn check_channels(channels: usize) -> Result<(), String> {
+    if channels == 0 || channels > MAX_CHANNELS {
+        Err(format!("Unsupported channel count: {}, expected 1 to {}", channels, MAX_CHANNELS))
+    } else {
+        Ok(())
+    }
+}
+
+/// 多声道输入的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelMode {
+    /// 各声道取平均混成单声道，用一个识别流识别
+    #[default]
+    Downmix,
+    /// 每个声道使用独立的识别流，结果按声道标注
+    Separate,
+}
+
+impl FromStr for ChannelMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "downmix" => Ok(ChannelMode::Downmix),
+            "separate" => Ok(ChannelMode::Separate),
+            _ => Err(format!("Unknown channel mode: {}", s)),
+        }
+    }
+}
+
+impl ChannelMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelMode::Downmix => "downmix",
+            ChannelMode::Separate => "separate",
+        }
+    }
+}
+
+/// 输入音频的采样格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 有符号 16 位小端
+    #[default]
+    S16Le,
+    /// 有符号 16 位大端
+    S16Be,
+    /// 无符号 8 位，128 为零点
+    U8,
+    /// 有符号 24 位小端，每个采样占 3 字节
+    S24Le,
+    /// 有符号 32 位小端
+    S32Le,
+    /// 32 位小端浮点，取值范围 [-1, 1]
+    F32Le,
+    /// G.711 μ-law，每个采样 1 字节
+    MuLaw,
+    /// G.711 A-law，每个采样 1 字节
+    ALaw,
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "s16le" => Ok(SampleFormat::S16Le),
+            "s16be" => Ok(SampleFormat::S16Be),
+            "u8" => Ok(SampleFormat::U8),
+            "s24le" => Ok(SampleFormat::S24Le),
+            "s32le" => Ok(SampleFormat::S32Le),
+            "f32le" => Ok(SampleFormat::F32Le),
+            "mulaw" | "ulaw" => Ok(SampleFormat::MuLaw),
+            "alaw" => Ok(SampleFormat::ALaw),
+            _ => Err(format!("Unknown sample format: {}", s)),
+        }
+    }
+}
+
+impl SampleFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SampleFormat::S16Le => "s16le",
+            SampleFormat::S16Be => "s16be",
+            SampleFormat::U8 => "u8",
+            SampleFormat::S24Le => "s24le",
+            SampleFormat::S32Le => "s32le",
+            SampleFormat::F32Le => "f32le",
+            SampleFormat::MuLaw => "mulaw",
+            SampleFormat::ALaw => "alaw",
+        }
+    }
+
+    /// WAV 头部对应的采样格式
+    pub fn from_wav(format: &WavFormat) -> Result<Self, String> {
+        match (format.format_tag, format.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => Ok(SampleFormat::U8),
+            (WAVE_FORMAT_PCM, 16) => Ok(SampleFormat::S16Le),
+            (WAVE_FORMAT_PCM, 24) => Ok(SampleFormat::S24Le),
+            (WAVE_FORMAT_PCM, 32) => Ok(SampleFormat::S32Le),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => Ok(SampleFormat::F32Le),
+            (WAVE_FORMAT_MULAW, 8) => Ok(SampleFormat::MuLaw),
+            (WAVE_FORMAT_ALAW, 8) => Ok(SampleFormat::ALaw),
+            _ => Err(format!("Unsupported wav format: {:?}", format)),
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            SampleFormat::U8 | SampleFormat::MuLaw | SampleFormat::ALaw => 1,
+            SampleFormat::S16Le | SampleFormat::S16Be => 2,
+            SampleFormat::S24Le => 3,
+            SampleFormat::S32Le | SampleFormat::F32Le => 4,
+        }
+    }
+
+    /// 把完整的采样转成 [-1, 1] 区间的 f32，`bytes` 的长度必须是 `bytes_per_sample` 的整数倍
+    pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        let samples = bytes.chunks_exact(self.bytes_per_sample());
+        match self {
+            SampleFormat::S16Le => samples.map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32767f32).collect(),
+            SampleFormat::S16Be => samples.map(|b| i16::from_be_bytes([b[0], b[1]]) as f32 / 32767f32).collect(),
+            SampleFormat::U8 => samples.map(|b| (b[0] as f32 - 128f32) / 128f32).collect(),
+            // 放到 i32 的高 24 位，由算术右移完成符号扩展
+            SampleFormat::S24Le => samples.map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608f32).collect(),
+            SampleFormat::S32Le => samples.map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648f32).collect(),
+            // 浮点输入可能越界，截到 [-1, 1]
+            SampleFormat::F32Le => samples.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]).clamp(-1f32, 1f32)).collect(),
+            SampleFormat::MuLaw => samples.map(|b| mulaw_to_i16(b[0]) as f32 / 32767f32).collect(),
+            SampleFormat::ALaw => samples.map(|b| alaw_to_i16(b[0]) as f32 / 32767f32).collect(),
+        }
+    }
+}
+
+// G.711 μ-law 解码，码字按位取反存储，符号位为 1 表示负数
+fn mulaw_to_i16(value: u8) -> i16 {
+    let value = !value;
+    let exponent = (value >> 4) & 0x07;
+    let mantissa = (value & 0x0F) as i32;
+    let magnitude = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+    if value & 0x80 != 0 { -magnitude as i16 } else { magnitude as i16 }
+}
+
+// G.711 A-law 解码，码字偶数位取反存储，符号位为 1 表示正数
+fn alaw_to_i16(value: u8) -> i16 {
+    let value = value ^ 0x55;
+    let exponent = (value >> 4) & 0x07;
+    let mantissa = (value & 0x0F) as i32;
+    let magnitude = if exponent == 0 {
+        (mantissa << 4) + 8
+    } else {
+        ((mantissa << 4) + 0x108) << (exponent - 1)
+    };
+    if value & 0x80 != 0 { magnitude as i16 } else { -magnitude as i16 }
+}
+
+/// 会话的音频处理流程：剥离容器头、按采样切分字节流、解码成 f32、拆分或混合声道，
+/// 再重采样到识别流的采样率。
+///
+/// 输出按识别流分组，混合成单声道时只有一组，分别识别时每个声道一组。
+/// 压缩音频先整个缓存，输入结束时一次解码。
+pub struct AudioPipeline {
+    config: AudioConfig,
+    output_rate: u32,
+    wav_parser: WavStreamParser,
+    compressed: Vec<u8>,
+    // 输入格式在收到第一块音频后才能确定（WAV 需要先解析头部）
+    decoder: Option<SessionDecoder>,
+}
+
+struct SessionDecoder {
+    format: SampleFormat,
+    channels: usize,
+    channel_mode: ChannelMode,
+    framer: SampleFramer,
+    resamplers: Vec<StreamResampler>,
+}
+
+impl SessionDecoder {
+    fn new(format: SampleFormat, sample_rate: u32, channels: usize, channel_mode: ChannelMode, output_rate: u32) -> Result<Self, String> {
+        check_channels(channels)?;
+        let outputs = match channel_mode {
+            ChannelMode::Downmix => 1,
+            ChannelMode::Separate => channels,
+        };
+        Ok(Self {
+            format,
+            channels,
+            channel_mode,
+            framer: SampleFramer::new(format.bytes_per_sample() * channels),
+            resamplers: (0..outputs).map(|_| StreamResampler::new(sample_rate, output_rate))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<Vec<f32>>, String> {
+        // 跨读取边界的不完整采样帧留到下一块数据再拼接
+        let samples = self.format.decode(&self.framer.push(bytes));
+        self.process(samples)
+    }
+
+    /// 按声道交错排列的采样，拆分或混合声道后重采样
+    fn process(&mut self, samples: Vec<f32>) -> Result<Vec<Vec<f32>>, String> {
+        let outputs = match self.channel_mode {
+            ChannelMode::Downmix if self.channels > 1 => vec![downmix(&samples, self.channels)],
+            ChannelMode::Downmix => vec![samples],
+            ChannelMode::Separate => deinterleave(&samples, self.channels),
+        };
+        self.resamplers.iter_mut().zip(outputs)
+            .map(|(resampler, samples)| resampler.process(&samples))
+            .collect()
+    }
+
+    fn flush(&mut self) -> Result<Vec<Vec<f32>>, String> {
+        if self.framer.pending() > 0 {
+            eprintln!("Dropping {} trailing bytes of incomplete sample", self.framer.pending());
+        }
+        self.resamplers.iter_mut().map(|resampler| resampler.flush()).collect()
+    }
+}
+
+impl AudioPipeline {
+    pub fn new(config: AudioConfig, output_rate: u32) -> Self {
+        Self {
+            config,
+            output_rate,
+            wav_parser: WavStreamParser::default(),
+            compressed: Vec::new(),
+            decoder: None,
+        }
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// 送入新收到的字节，返回每个识别流可以送去识别的采样，数据不足时返回空
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<Vec<f32>>, String> {
+        let data;
+        let (bytes, sample_format, sample_rate, channels) = match self.config.container {
+            AudioContainer::Raw => (bytes, self.config.sample_format, self.config.sample_rate, self.config.channels),
+            AudioContainer::Wav => {
+                data = self.wav_parser.push(bytes)?;
+                match self.wav_parser.format() {
+                    Some(format) => (&data[..], SampleFormat::from_wav(&format)?, format.sample_rate, format.channels as usize),
+                    None => return Ok(Vec::new()),
+                }
+            },
+            AudioContainer::Compressed => {
+                if self.compressed.len() + bytes.len() > MAX_COMPRESSED_SIZE {
+                    return Err(format!("Compressed audio exceeds {} bytes", MAX_COMPRESSED_SIZE));
+                }
+                self.compressed.extend_from_slice(bytes);
+                // 能解码的格式要等收完整个文件，不支持的 Opus 在文件头到达时就拒绝
+                if codec::is_opus(&self.compressed) {
+                    return Err(codec::OPUS_UNSUPPORTED.to_string());
+                }
+                return Ok(Vec::new());
+            },
+        };
+        let decoder = match self.decoder.take() {
+            Some(decoder) => decoder,
+            None => SessionDecoder::new(sample_format, sample_rate, channels, self.config.channel_mode, self.output_rate)?,
+        };
+        self.decoder.insert(decoder).push(bytes)
+    }
+
+    /// 输入结束，取出每个识别流剩余的采样，之后可以开始新的会话。
+    ///
+    /// 压缩音频在这里解码，可能比较耗时，不要在异步任务中直接调用。
+    pub fn finish(&mut self) -> Result<Vec<Vec<f32>>, String> {
+        let tail = if self.config.container == AudioContainer::Compressed {
+            self.finish_compressed()
+        } else {
+            match &mut self.decoder {
+                Some(decoder) => decoder.flush(),
+                None => Ok(Vec::new()),
+            }
+        };
+        self.reset();
+        tail
+    }
+
+    fn finish_compressed(&mut self) -> Result<Vec<Vec<f32>>, String> {
+        if self.compressed.is_empty() {
+            return Ok(Vec::new());
+        }
+        let audio = codec::decode(std::mem::take(&mut self.compressed))?;
+        let mut decoder = SessionDecoder::new(SampleFormat::F32Le, audio.sample_rate, audio.channels, self.config.channel_mode, self.output_rate)?;
+        let mut outputs = decoder.process(audio.samples)?;
+        for (output, tail) in outputs.iter_mut().zip(decoder.flush()?) {
+            output.extend(tail);
+        }
+        Ok(outputs)
+    }
+
+    pub fn reset(&mut self) {
+        self.wav_parser.reset();
+        self.compressed.clear();
+        self.decoder = None;
+    }
+}
+
+pub(crate) fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    samples.chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+fn deinterleave(samples: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    (0..channels)
+        .map(|channel| samples.iter().skip(channel).step_by(channels).copied().collect())
+        .collect()
+}
+
+/// 按帧切分收到的字节流。
+///
+/// TCP 读到的数据块边界是任意的，一个采样帧可能被拆在两次读取之间，
+/// 不足一帧的尾部字节会留到下一次读取时再拼接，保证交给识别流的总是完整的采样。
+#[derive(Debug)]
+pub struct SampleFramer {
+    frame_size: usize,
+    pending: Vec<u8>,
+}
+
+impl SampleFramer {
+    pub fn new(frame_size: usize) -> Self {
+        Self {
+            frame_size: frame_size.max(1),
+            pending: Vec::new(),
+        }
+    }
+
+    /// 送入新收到的字节，返回其中完整的帧，剩余字节留到下一次
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(bytes);
+        let len = self.pending.len() - self.pending.len() % self.frame_size;
+        let rest = self.pending.split_off(len);
+        std::mem::replace(&mut self.pending, rest)
+    }
+
+    /// 输入结束时还未凑成完整一帧的字节数
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// 流式重采样，输入可以任意切块，输出与一次性重采样整段音频的结果对齐。
+///
+/// 使用带窗 sinc 插值，不足一块的输入缓存到下一次，`flush` 时取出滤波器中剩余的输出并截到输入时长，
+/// 所以输出的采样数与输入时长严格对应。`SincFixedIn` 从 `-sinc_len / 2` 开始插值，已经抵消了滤波器的延迟，
+/// `output_delay` 只是近似值，不需要再从开头丢弃。输入输出采样率相同时直接透传。
+pub struct StreamResampler {
+    input_rate: u32,
+    output_rate: u32,
+    inner: Option<SincFixedIn<f32>>,
+    pending: Vec<f32>,
+    input_len: usize,
+    output_len: usize,
+}
+
+impl StreamResampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Result<Self, String> {
+        if input_rate == 0 || output_rate == 0 {
+            return Err(format!("Invalid sample rate: {} -> {}", input_rate, output_rate));
+        }
+        let inner = if input_rate == output_rate {
+            None
+        } else {
+            let parameters = SincInterpolationParameters {
+                sinc_len: 256,
+                f_cutoff: 0.95,
+                oversampling_factor: 128,
+                interpolation: SincInterpolationType::Cubic,
+                window: WindowFunction::BlackmanHarris2,
+            };
+            let ratio = output_rate as f64 / input_rate as f64;
+            Some(SincFixedIn::new(ratio, 1.0, parameters, RESAMPLER_CHUNK_SIZE, 1)
+                .map_err(|e| format!("Failed to create resampler: {}", e))?)
+        };
+        Ok(Self {
+            input_rate,
+            output_rate,
+            inner,
+            pending: Vec::new(),
+            input_len: 0,
+            output_len: 0,
+        })
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>, String> {
+        let Some(inner) = &mut self.inner else {
+            return Ok(samples.to_vec());
+        };
+        self.input_len += samples.len();
+        self.pending.extend_from_slice(samples);
+        let mut output = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= inner.input_frames_next() {
+            let end = offset + inner.input_frames_next();
+            let resampled = inner.process(&[&self.pending[offset..end]], None)
+                .map_err(|e| format!("Failed to resample: {}", e))?;
+            output.extend_from_slice(&resampled[0]);
+            offset = end;
+        }
+        self.pending.drain(..offset);
+        self.output_len += output.len();
+        Ok(output)
+    }
+
+    /// 输入结束，处理缓存中不足一块的采样并取出滤波器中剩余的输出
+    pub fn flush(&mut self) -> Result<Vec<f32>, String> {
+        let Some(inner) = &mut self.inner else {
+            return Ok(Vec::new());
+        };
+        let expected = (self.input_len as f64 * self.output_rate as f64 / self.input_rate as f64).round() as usize;
+        let mut output = Vec::new();
+        if !self.pending.is_empty() {
+            let resampled = inner.process_partial(Some(&[&self.pending[..]]), None)
+                .map_err(|e| format!("Failed to resample: {}", e))?;
+            output.extend_from_slice(&resampled[0]);
+            self.pending.clear();
+        }
+        while self.output_len + output.len() < expected {
+            let resampled = inner.process_partial(None::<&[&[f32]]>, None)
+                .map_err(|e| format!("Failed to resample: {}", e))?;
+            output.extend_from_slice(&resampled[0]);
+        }
+        output.truncate(expected.saturating_sub(self.output_len));
+        self.output_len += output.len();
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / rate as f32).sin() * 0.5).collect()
+    }
+
+    fn resample_all(input_rate: u32, output_rate: u32, samples: &[f32], chunk: usize) -> Vec<f32> {
+        let mut resampler = StreamResampler::new(input_rate, output_rate).unwrap();
+        let mut output = Vec::new();
+        for chunk in samples.chunks(chunk) {
+            output.extend(resampler.process(chunk).unwrap());
+        }
+        output.extend(resampler.flush().unwrap());
+        output
+    }
+
+    #[test]
+    fn decodes_g711_codewords() {
+        for (codeword, expected) in [(0x00, -32124), (0x7F, 0), (0x80, 32124), (0xFF, 0)] {
+            assert_eq!(mulaw_to_i16(codeword), expected, "mulaw {:#04x}", codeword);
+        }
+        for (codeword, expected) in [(0xD5, 8), (0x55, -8), (0xAA, 32256), (0x2A, -32256)] {
+            assert_eq!(alaw_to_i16(codeword), expected, "alaw {:#04x}", codeword);
+        }
+        assert_eq!(SampleFormat::MuLaw.decode(&[0x00, 0xFF]), [-32124f32 / 32767f32, 0.0]);
+        assert_eq!(SampleFormat::ALaw.decode(&[0xD5, 0x55]), [8f32 / 32767f32, -8f32 / 32767f32]);
+    }
+
+    #[test]
+    fn decodes_pcm_sample_formats() {
+        let cases: [(SampleFormat, &[u8], &[f32]); 6] = [
+            (SampleFormat::S16Le, &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80], &[0.0, 1.0, -1.0]),
+            (SampleFormat::S16Be, &[0x7F, 0xFF, 0x80, 0x01], &[1.0, -1.0]),
+            (SampleFormat::U8, &[0x80, 0x00, 0xC0], &[0.0, -1.0, 0.5]),
+            // 24 位负数需要符号扩展
+            (SampleFormat::S24Le, &[0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x80, 0x00, 0x00, 0xC0], &[-1.0 / 8388608.0, -1.0, -0.5]),
+            (SampleFormat::S24Le, &[0x00, 0x00, 0x40, 0x00, 0x00, 0x00], &[0.5, 0.0]),
+            (SampleFormat::S32Le, &[0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x40], &[-1.0, 0.5]),
+        ];
+        for (format, bytes, expected) in cases {
+            assert_eq!(format.decode(bytes), expected, "{}", format.as_str());
+        }
+        // 越界的浮点采样截到 [-1, 1]
+        let bytes: Vec<u8> = [0.25f32, 1.5, -3.0, -1.0].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        assert_eq!(SampleFormat::F32Le.decode(&bytes), [0.25, 1.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn framer_carries_partial_frames_across_pushes() {
+        // 双声道 s16le 每帧 4 字节，用奇数字节切块，帧被拆在多次读取之间
+        let bytes: Vec<u8> = (0..41).collect();
+        for chunk in [1, 3, 5, 7] {
+            let mut framer = SampleFramer::new(4);
+            let mut output = Vec::new();
+            let mut received = 0;
+            for chunk in bytes.chunks(chunk) {
+                received += chunk.len();
+                output.extend(framer.push(chunk));
+                // 只输出整帧，其余字节都留在 pending 中
+                assert_eq!(output.len() % 4, 0);
+                assert_eq!(output.len() + framer.pending(), received);
+            }
+            assert_eq!(output, bytes[..40], "chunk {}", chunk);
+            assert_eq!(framer.pending(), 1, "chunk {}", chunk);
+        }
+        let mut framer = SampleFramer::new(4);
+        assert!(framer.push(&[1, 2, 3]).is_empty());
+        assert_eq!(framer.pending(), 3);
+        assert_eq!(framer.push(&[4, 5, 6, 7, 8, 9]), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(framer.pending(), 1);
+        framer.reset();
+        assert_eq!(framer.pending(), 0);
+    }
+
+    #[test]
+    fn pipeline_splits_or_downmixes_interleaved_channels() {
+        let frames: [[i16; 2]; 3] = [[1000, -1000], [2000, 0], [3000, 3000]];
+        let mut bytes: Vec<u8> = frames.iter().flatten().flat_map(|sample| sample.to_le_bytes()).collect();
+        // 末尾不完整的采样在输入结束时丢弃
+        bytes.push(0x7F);
+        let scaled = |samples: &[i16]| samples.iter().map(|&sample| sample as f32 / 32767f32).collect::<Vec<_>>();
+        for (channel_mode, expected) in [
+            (ChannelMode::Separate, vec![scaled(&[1000, 2000, 3000]), scaled(&[-1000, 0, 3000])]),
+            (ChannelMode::Downmix, vec![scaled(&[0, 1000, 3000])]),
+        ] {
+            let config = AudioConfig { channels: 2, channel_mode, ..AudioConfig::default() };
+            let mut pipeline = AudioPipeline::new(config, 16000);
+            let mut outputs = vec![Vec::new(); expected.len()];
+            for chunk in bytes.chunks(3) {
+                for (output, samples) in outputs.iter_mut().zip(pipeline.push(chunk).unwrap()) {
+                    output.extend(samples);
+                }
+            }
+            for (output, samples) in outputs.iter_mut().zip(pipeline.finish().unwrap()) {
+                output.extend(samples);
+            }
+            assert_eq!(outputs, expected, "{}", channel_mode.as_str());
+        }
+    }
+
+    #[test]
+    fn resampled_length_and_timing_follow_input() {
+        for (input_rate, output_rate, len, expected) in [(8000, 16000, 12000, 24000), (48000, 16000, 48123, 16041)] {
+            // 在输入的第一秒处放一个脉冲，输出的峰值应该落在输出的第一秒处
+            let mut samples = vec![0.0; len];
+            samples[input_rate as usize] = 1.0;
+            let output = resample_all(input_rate, output_rate, &samples, len);
+            assert_eq!(output.len(), expected, "{} -> {}", input_rate, output_rate);
+            let peak = (0..output.len()).max_by(|&a, &b| output[a].abs().total_cmp(&output[b].abs())).unwrap();
+            assert!(peak.abs_diff(output_rate as usize) <= 1, "{} -> {}: peak at {}", input_rate, output_rate, peak);
+        }
+    }
+
+    #[test]
+    fn chunked_input_matches_single_call() {
+        for (input_rate, output_rate) in [(8000, 16000), (48000, 16000), (44100, 16000)] {
+            let samples = sine(input_rate, 30001);
+            let whole = resample_all(input_rate, output_rate, &samples, samples.len());
+            for chunk in [1, 160, 1023, 4097] {
+                assert_eq!(resample_all(input_rate, output_rate, &samples, chunk), whole, "{} -> {} by {}", input_rate, output_rate, chunk);
+            }
+        }
+        // 采样率相同时直接透传
+        let samples = sine(16000, 1000);
+        assert_eq!(resample_all(16000, 16000, &samples, 7), samples);
+    }
+}
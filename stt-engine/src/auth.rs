@@ -0,0 +1,300 @@
+use std::{collections::HashMap, path::Path, sync::{Arc, Mutex, MutexGuard}, time::{SystemTime, UNIX_EPOCH}};
+
+use ring::hmac;
+
+use crate::protocol::{ErrorCode, ServerError};
+
+// 一天的秒数，每日配额按 UTC 日期重置
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// 密钥文件中的一个密钥，配额为 0 表示不限制
+struct Key {
+    id: String,
+    secret: String,
+    max_sessions: usize,
+    daily_minutes: u64,
+}
+
+// 一个密钥当前的会话数和当天已识别的音频时长
+#[derive(Default)]
+struct Usage {
+    active: usize,
+    day: u64,
+    seconds: f64,
+}
+
+/// 按本地密钥文件校验客户端凭证，并限制每个密钥的并发会话数和每日音频时长。
+///
+/// 凭证可以直接是密钥，也可以是用密钥签名的令牌 `<id>.<过期时间>.<签名>`，见 [`sign_token`]。
+/// 用量只保存在内存中，服务重启后重新计算。
+pub struct Authenticator {
+    keys: Vec<Key>,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Authenticator {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read key file {}: {}", path.display(), e))?;
+        Self::parse(&content)
+    }
+
+    /// 每行一个密钥：`<id> <secret> <max_sessions> <daily_minutes>`，`#` 开头的行是注释
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut keys: Vec<Key> = Vec::new();
+        for (i, line) in content.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [id, secret, max_sessions, daily_minutes] = fields[..] else {
+                return Err(format!("Line {}: expected <id> <secret> <max_sessions> <daily_minutes>", i));
+            };
+            // 令牌用 `.` 分隔各部分，密钥 ID 中不能出现
+            if id.contains('.') {
+                return Err(format!("Line {}: key id must not contain '.': {}", i, id));
+            }
+            if keys.iter().any(|key| key.id == id) {
+                return Err(format!("Line {}: duplicate key id: {}", i, id));
+            }
+            keys.push(Key {
+                id: id.to_string(),
+                secret: secret.to_string(),
+                max_sessions: max_sessions.parse().map_err(|_| format!("Line {}: invalid max_sessions: {}", i, max_sessions))?,
+                daily_minutes: daily_minutes.parse().map_err(|_| format!("Line {}: invalid daily_minutes: {}", i, daily_minutes))?,
+            });
+        }
+        if keys.is_empty() {
+            return Err("No key found in key file".to_string());
+        }
+        Ok(Self { keys, usage: Mutex::new(HashMap::new()) })
+    }
+
+    /// 校验凭证并占用一个会话名额，返回的 `Lease` 在会话结束时释放
+    pub fn admit(self: &Arc<Self>, credential: Option<&str>) -> Result<Lease, ServerError> {
+        let unauthorized = |message: &str| ServerError::new(ErrorCode::Unauthorized, message);
+        let credential = credential.filter(|credential| !credential.is_empty()).ok_or(unauthorized("Missing credential"))?;
+        let index = self.find(credential).ok_or(unauthorized("Invalid credential"))?;
+        let key = &self.keys[index];
+        let mut usage = self.usage();
+        let usage = usage.entry(key.id.clone()).or_default();
+        usage.roll(today());
+        if key.max_sessions > 0 && usage.active >= key.max_sessions {
+            return Err(ServerError::new(ErrorCode::QuotaExceeded,
+                format!("Too many concurrent sessions for key {}, max {}", key.id, key.max_sessions)));
+        }
+        if key.daily_minutes > 0 && usage.seconds >= (key.daily_minutes * 60) as f64 {
+            return Err(ServerError::new(ErrorCode::QuotaExceeded,
+                format!("Daily quota of {} minutes used up for key {}", key.daily_minutes, key.id)));
+        }
+        usage.active += 1;
+        Ok(Lease { authenticator: self.clone(), key: index })
+    }
+
+    // 先按密钥匹配，再按令牌校验，返回密钥的序号
+    fn find(&self, credential: &str) -> Option<usize> {
+        // 逐个比较所有密钥，不提前返回
+        let matched = self.keys.iter().enumerate().fold(None, |matched, (i, key)| {
+            if constant_time_eq(key.secret.as_bytes(), credential.as_bytes()) { Some(i) } else { matched }
+        });
+        matched.or_else(|| self.verify_token(credential))
+    }
+
+    fn verify_token(&self, token: &str) -> Option<usize> {
+        let (message, signature) = token.rsplit_once('.')?;
+        let (id, expires) = message.split_once('.')?;
+        let index = self.keys.iter().position(|key| key.id == id)?;
+        let key = &self.keys[index];
+        if expires.parse::<u64>().ok()? < now() {
+            return None;
+        }
+        let signature = decode_hex(signature)?;
+        hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, key.secret.as_bytes()), message.as_bytes(), &signature).ok()?;
+        Some(index)
+    }
+
+    fn usage(&self) -> MutexGuard<'_, HashMap<String, Usage>> {
+        self.usage.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Usage {
+    // 跨过 UTC 零点后重新计算当天的用量
+    fn roll(&mut self, day: u64) {
+        if self.day != day {
+            self.day = day;
+            self.seconds = 0.0;
+        }
+    }
+}
+
+/// 一个已通过认证的会话占用的名额，丢弃时释放
+pub struct Lease {
+    authenticator: Arc<Authenticator>,
+    key: usize,
+}
+
+impl Lease {
+    fn key(&self) -> &Key {
+        &self.authenticator.keys[self.key]
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key().id
+    }
+
+    /// 记录识别的音频时长，超过当天的配额时返回错误，由调用方结束会话
+    pub fn record(&self, seconds: f64) -> Result<(), ServerError> {
+        let key = self.key();
+        let mut usage = self.authenticator.usage();
+        let usage = usage.entry(key.id.clone()).or_default();
+        usage.roll(today());
+        usage.seconds += seconds;
+        if key.daily_minutes > 0 && usage.seconds > (key.daily_minutes * 60) as f64 {
+            return Err(ServerError::new(ErrorCode::QuotaExceeded,
+                format!("Daily quota of {} minutes exceeded for key {}", key.daily_minutes, key.id)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(usage) = self.authenticator.usage().get_mut(&self.key().id) {
+            usage.active = usage.active.saturating_sub(1);
+        }
+    }
+}
+
+/// 用密钥签发一个到 `expires`（Unix 时间戳，秒）为止有效的令牌
+pub fn sign_token(id: &str, secret: &str, expires: u64) -> String {
+    let message = format!("{}.{}", id, expires);
+    let signature = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()), message.as_bytes());
+    let hex = signature.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("{}.{}", message, hex)
+}
+
+/// WebSocket 客户端不能设置请求头时，凭证放在这个子协议之后
+pub const BEARER_PROTOCOL: &str = "bearer";
+
+/// 取出 `Sec-WebSocket-Protocol: bearer, <credential>` 中的凭证
+pub fn bearer_protocol(protocols: &str) -> Option<&str> {
+    let mut protocols = protocols.split(',').map(str::trim);
+    protocols.position(|protocol| protocol.eq_ignore_ascii_case(BEARER_PROTOCOL))?;
+    protocols.next().filter(|credential| !credential.is_empty())
+}
+
+/// 取出 `Authorization: Bearer <credential>` 中的凭证
+pub fn bearer(authorization: &str) -> Option<&str> {
+    let (scheme, credential) = authorization.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| credential.trim())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+fn today() -> u64 {
+    now() / SECONDS_PER_DAY
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator(keys: &str) -> Arc<Authenticator> {
+        Arc::new(Authenticator::parse(keys).unwrap())
+    }
+
+    fn code(result: Result<Lease, ServerError>) -> ErrorCode {
+        result.err().expect("credential should be rejected").code
+    }
+
+    #[test]
+    fn verifies_tokens() {
+        let auth = authenticator("alice secret-a 0 0\nbob secret-b 0 0");
+        let expires = now() + 60;
+        assert_eq!(auth.admit(Some(&sign_token("alice", "secret-a", expires))).unwrap().key_id(), "alice");
+        assert_eq!(auth.admit(Some("secret-b")).unwrap().key_id(), "bob");
+
+        // 过期、用错密钥签名、篡改内容和格式错误的令牌都被拒绝
+        assert_eq!(code(auth.admit(Some(&sign_token("alice", "secret-a", now() - 1)))), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.admit(Some(&sign_token("alice", "secret-b", expires)))), ErrorCode::Unauthorized);
+        let token = sign_token("alice", "secret-a", expires);
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        assert_eq!(code(auth.admit(Some(&format!("bob.{}.{}", expires, signature)))), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.admit(Some(&format!("alice.{}.{}", expires + 1, signature)))), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.admit(Some(&format!("{}zz", &token[..token.len() - 2])))), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.admit(Some("alice.1.abc"))), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.admit(Some(""))), ErrorCode::Unauthorized);
+        assert_eq!(code(auth.admit(None)), ErrorCode::Unauthorized);
+    }
+
+    #[test]
+    fn reads_bearer_credentials() {
+        assert_eq!(bearer("Bearer token"), Some("token"));
+        assert_eq!(bearer(" bearer  token "), Some("token"));
+        assert_eq!(bearer("Basic token"), None);
+        assert_eq!(bearer_protocol("bearer, token"), Some("token"));
+        assert_eq!(bearer_protocol("json, Bearer,token"), Some("token"));
+        assert_eq!(bearer_protocol("bearer"), None);
+        assert_eq!(bearer_protocol("json, token"), None);
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret1"));
+        assert!(!constant_time_eq(b"secret", b""));
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn limits_concurrent_sessions() {
+        let auth = authenticator("alice secret 2 0");
+        let first = auth.admit(Some("secret")).unwrap();
+        let second = auth.admit(Some("secret")).unwrap();
+        assert_eq!(code(auth.admit(Some("secret"))), ErrorCode::QuotaExceeded);
+
+        // 丢弃 Lease 后名额释放
+        drop(first);
+        let third = auth.admit(Some("secret")).unwrap();
+        assert_eq!(code(auth.admit(Some("secret"))), ErrorCode::QuotaExceeded);
+        drop(second);
+        drop(third);
+        assert_eq!(auth.usage().get("alice").unwrap().active, 0);
+    }
+
+    #[test]
+    fn limits_daily_minutes() {
+        let auth = authenticator("alice secret 0 1\nbob other 0 0");
+        let lease = auth.admit(Some("secret")).unwrap();
+        assert!(lease.record(30.0).is_ok());
+        assert!(lease.record(30.0).is_ok());
+        assert_eq!(lease.record(0.5).unwrap_err().code, ErrorCode::QuotaExceeded);
+        drop(lease);
+        assert_eq!(code(auth.admit(Some("secret"))), ErrorCode::QuotaExceeded);
+
+        // 其他密钥不受影响，不限时长的密钥可以一直记录
+        let lease = auth.admit(Some("other")).unwrap();
+        assert!(lease.record(1e6).is_ok());
+
+        // 跨天后用量清零
+        auth.usage().get_mut("alice").unwrap().day = today() - 1;
+        assert!(auth.admit(Some("secret")).is_ok());
+    }
+}
@@ -2,9 +2,9 @@ use std::sync::{Arc, Mutex};
 
 use tokio::{signal::ctrl_c, sync::mpsc, task::JoinHandle};
 
-use crate::client;
+use crate::{client::{self, Transport}, protocol::ProtocolMode};
 
-pub async fn run_benchmark(ip: String, port: u16, max_clients: usize) {
+pub async fn run_benchmark(transport: Transport, auth: Option<String>, max_clients: usize, protocol: ProtocolMode) {
     tokio::select! {
         _ = async move {
             let (tx, mut rx) = mpsc::channel::<JoinHandle<()>>(max_clients);
@@ -13,20 +13,14 @@ pub async fn run_benchmark(ip: String, port: u16, max_clients: usize) {
                 let max_support = max_clients;
                 while let Some(handle) = rx.recv().await {
                     // println!("Received handle");
-                    match handles.lock() {
-                        Ok(mut handles) => {
-                            // 如果handles长度max_support, 则等待handles中的任务完成后再添加新任务
-                            while handles.len() >= max_support {
-                                for i in 0..handles.len() {
-                                    if handles[i].is_finished() {
-                                        handles.remove(i);
-                                        break;
-                                    }
-                                }
+                    if let Ok(mut handles) = handles.lock() {
+                        // 如果handles长度max_support, 则等待handles中的任务完成后再添加新任务
+                        while handles.len() >= max_support {
+                            if let Some(i) = handles.iter().position(|h| h.is_finished()) {
+                                handles.remove(i);
                             }
-                            handles.push(handle);
                         }
-                        Err(_) => {}
+                        handles.push(handle);
                     }
                 }
             });
@@ -36,10 +30,11 @@ pub async fn run_benchmark(ip: String, port: u16, max_clients: usize) {
                 if i > 100 {
                     i = 1;
                 }
-                let ip = ip.clone();
+                let transport = transport.clone();
+                let auth = auth.clone();
                 let handle = tokio::spawn(async move {
                     let start_time = std::time::Instant::now();
-                    if let Ok(result) = client::run_with(ip, port, wav_file.clone(), false).await {
+                    if let Ok(result) = client::run_on(transport, auth, wav_file.clone(), protocol, false).await {
                         if result.clone().is_connect_success() {
                             let duration = start_time.elapsed().as_secs();
                             println!("Transcribe file {} spend {}s, result: {:?}", wav_file, duration, result);
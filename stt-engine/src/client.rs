@@ -1,9 +1,15 @@
-use std::{io::Read, time::Duration};
+use std::{io::Read, path::PathBuf, time::Duration};
 use derive_new::new;
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, time::sleep};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpStream, UnixStream}, time::{sleep, timeout}};
 
+use crate::{audio::SampleFormat, codec, protocol::{read_frame, start_session, write_frame, ErrorCode, Frame, FrameDecoder, HandshakeError, ProtocolMode, ServerError, StartConfig}, transcript::{TranscriptCollector, TranscriptEvent}, tls::TlsClient, wav::AudioContainer};
+
+// 分帧协议下每个 Audio 帧携带的字节数，16kHz 16 位单声道为 1 秒
+const AUDIO_FRAME_SIZE: usize = 32000;
+
+/// 一次客户端运行的结果
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum RunningResult {
+pub enum RunningResult {
     Succeccess,
     SendFailed,
     SendTimeout,
@@ -11,9 +17,13 @@ enum RunningResult {
     ReadFailed,
     ConnectFailed,
     ConnectTimeout,
+    HandshakeFailed,
+    /// 服务端返回了错误，例如繁忙、音频格式不支持或识别出错
+    ServerError(ErrorCode),
 }
 
 #[derive(Debug, Clone, new)]
+#[allow(clippy::too_many_arguments)]
 pub struct RunningRecord {
     _wav_file: String,
     _running_result: RunningResult,
@@ -29,17 +39,99 @@ impl RunningRecord {
     pub fn is_connect_success(self) -> bool {
         self._running_result == RunningResult::Succeccess
     }
+
+    pub fn running_result(&self) -> RunningResult {
+        self._running_result
+    }
+
+    /// 服务端返回的错误码，例如繁忙时是 `ErrorCode::Busy`
+    pub fn server_error(&self) -> Option<ErrorCode> {
+        match self._running_result {
+            RunningResult::ServerError(code) => Some(code),
+            _ => None,
+        }
+    }
 }
 
 unsafe impl Send for RunningRecord {}
 unsafe impl Sync for RunningRecord {}
 
-pub async fn run_with(ip: String, port: u16, wav_file: String, debug: bool) -> Result<RunningRecord, Box<dyn std::error::Error>> {
+/// 客户端连接服务端的方式，几种连接使用相同的协议
+#[derive(Debug, Clone)]
+pub enum Transport {
+    Tcp(String, u16),
+    // 服务端开启 TLS 时使用，证书按地址或者 `TlsClient` 中指定的名称校验
+    Tls(String, u16, TlsClient),
+    Unix(PathBuf),
+}
+
+impl Transport {
+    /// `unix:` 开头的地址是 Unix domain socket 的路径，其他地址使用 TCP 连接 `port`
+    pub fn parse(addr: &str, port: u16) -> Self {
+        match addr.strip_prefix("unix:") {
+            Some(path) => Transport::Unix(path.into()),
+            None => Transport::Tcp(addr.to_string(), port),
+        }
+    }
+
+    async fn connect(&self) -> std::io::Result<Box<dyn Connection>> {
+        match self {
+            Transport::Tcp(ip, port) => Ok(Box::new(TcpStream::connect(format!("{}:{}", ip, port)).await?)),
+            Transport::Tls(ip, port, tls) => {
+                let stream = TcpStream::connect(format!("{}:{}", ip, port)).await?;
+                Ok(Box::new(tls.connect(ip, stream).await?))
+            },
+            Transport::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+        }
+    }
+}
+
+// TCP、TLS 和 Unix domain socket 连接都按字节流读写
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+async fn send_audio<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8], protocol: ProtocolMode) -> std::io::Result<()> {
+    match protocol {
+        ProtocolMode::Raw => {
+            stream.write_all(data).await?;
+            // 关闭写端，通知服务端音频已发送完毕，服务端发送最后的结果后会关闭连接
+            stream.shutdown().await
+        },
+        ProtocolMode::Framed => {
+            for chunk in data.chunks(AUDIO_FRAME_SIZE) {
+                stream.write_all(&Frame::Audio(chunk.to_vec()).encode()).await?;
+            }
+            // End 帧表示音频已发送完毕，服务端发送最后的结果和 End 帧后会关闭连接
+            write_frame(stream, &Frame::End).await
+        },
+    }
+}
+
+/// 通过 TCP 连接服务端，`tls` 不为空时在 TCP 连接上建立 TLS
+pub async fn run_with(ip: String, port: u16, tls: Option<TlsClient>, auth: Option<String>, wav_file: String, protocol: ProtocolMode,
+    debug: bool) -> Result<RunningRecord, Box<dyn std::error::Error>> {
+    let transport = match tls {
+        Some(tls) => Transport::Tls(ip, port, tls),
+        None => Transport::Tcp(ip, port),
+    };
+    run_on(transport, auth, wav_file, protocol, debug).await
+}
+
+/// 服务端开启认证时 `auth` 是密钥或令牌，在 Start 帧中发送
+pub async fn run_on(transport: Transport, auth: Option<String>, wav_file: String, protocol: ProtocolMode, debug: bool) -> Result<RunningRecord, Box<dyn std::error::Error>> {
     // 读取WAV文件
     let start_time = std::time::Instant::now();
     let mut file = std::fs::File::open(wav_file.clone())?;
     let mut data = Vec::new();
     file.read_to_end(&mut data).unwrap();
+    // 解码 WAV、FLAC、MP3 等音频文件，转成服务端默认接受的 16kHz 单声道 16 位 PCM
+    let samples = codec::decode(data)
+        .and_then(|audio| audio.to_mono(16000))
+        .map_err(|e| format!("Failed to decode {}: {}", wav_file, e))?;
+    let data = samples.iter()
+        .flat_map(|sample| ((sample.clamp(-1f32, 1f32) * 32767f32) as i16).to_le_bytes())
+        .collect::<Vec<u8>>();
     let readfile_time = start_time.elapsed().as_nanos() as usize;
     if debug {
         println!("Connecting...");
@@ -50,7 +142,7 @@ pub async fn run_with(ip: String, port: u16, wav_file: String, debug: bool) -> R
     tokio::select! {
         stream = async move {
             let start_time = std::time::Instant::now();
-            match TcpStream::connect(format!("{}:{}", ip, port)).await {
+            match transport.connect().await {
                 Ok(stream) => {
                     let connecting_time = start_time.elapsed().as_nanos() as usize;
                     Ok((stream, connecting_time))
@@ -60,17 +152,47 @@ pub async fn run_with(ip: String, port: u16, wav_file: String, debug: bool) -> R
         } => {
             match stream {
                 Ok((mut stream, connecting_time)) => {
+                    let mut decoder = FrameDecoder::default();
+                    if protocol == ProtocolMode::Framed {
+                        // 声明发送的是 16kHz 单声道 16 位 PCM，等服务端分配好通道后再发送音频
+                        let start = StartConfig {
+                            container: Some(AudioContainer::Raw),
+                            sample_rate: Some(16000),
+                            sample_format: Some(SampleFormat::S16Le),
+                            channels: Some(1),
+                            auth,
+                            ..Default::default()
+                        };
+                        let handshake = timeout(total_timeout, start_session(&mut stream, &mut decoder, &start)).await
+                            .unwrap_or_else(|_| Err(HandshakeError::Failed("Handshake timeout".to_string())));
+                        match handshake {
+                            Ok((version, session_id)) => {
+                                if debug {
+                                    println!("Session {} started for {} with protocol version {}", session_id, wav_file, version);
+                                }
+                            },
+                            Err(e) => {
+                                if debug {
+                                    println!("Handshake failed for {}: {}", wav_file, e);
+                                }
+                                let running_result = match e {
+                                    HandshakeError::Rejected(error) => RunningResult::ServerError(error.code),
+                                    HandshakeError::Failed(_) => RunningResult::HandshakeFailed,
+                                };
+                                return Ok(RunningRecord::new(wav_file, running_result, true, 0, 0, 0, 0, "".to_string()));
+                            },
+                        }
+                    }
+
                     // 发送WAV文件数据
                     let mut _error_occurred = false;
                     let mut _running_result = RunningResult::Succeccess;
-                    let mut _transcribe_result = "".to_string();
-
-                    let timeout_duration = Duration::from_secs(2); // 设置超时时间为2秒
+                    let mut transcript = TranscriptCollector::default();
 
                     let mut start_time = std::time::Instant::now();
                     tokio::select! {
-                        result = stream.write_all(&data) => {
-                            if let Err(_) = result {
+                        result = send_audio(&mut stream, &data, protocol) => {
+                            if result.is_err() {
                                 return Ok(RunningRecord::new(wav_file, RunningResult::SendFailed, true, 0, 0, 0, 0, "".to_string()));
                             }
                         }
@@ -81,62 +203,119 @@ pub async fn run_with(ip: String, port: u16, wav_file: String, debug: bool) -> R
 
                     let sending_time = start_time.elapsed().as_nanos() as usize;
 
-                    // 读取服务器响应，直到连接关闭
-                    let mut buf = [0; 4096];
                     start_time = std::time::Instant::now();
-                    loop {
-                        tokio::select! {
-                            result = async {
-                                let n = stream.read(&mut buf).await;
-                                match n {
-                                    Ok(n) => {
-                                        if n == 0 {
-                                            _running_result = RunningResult::ReadEof;
-                                            return None; // 没有数据可读，连接可能已经关闭
+                    match protocol {
+                        ProtocolMode::Raw => {
+                            // 读取服务器响应，直到服务端关闭连接
+                            let mut buf = [0; 4096];
+                            // 一条消息可能被拆到多次读取中，未读完的行先缓存起来
+                            let mut pending = Vec::new();
+                            loop {
+                                tokio::select! {
+                                    result = async {
+                                        let n = stream.read(&mut buf).await;
+                                        match n {
+                                            Ok(n) => {
+                                                if n == 0 {
+                                                    // 没有收到任何最终结果就被关闭，说明服务端没有处理这次请求
+                                                    if _running_result == RunningResult::Succeccess && !transcript.has_final() {
+                                                        _running_result = RunningResult::ReadEof;
+                                                    }
+                                                    return None; // 没有数据可读，连接已经关闭
+                                                }
+                                                Some(buf[..n].to_vec())
+                                            },
+                                            Err(_) => {
+                                                _running_result = RunningResult::ReadFailed;
+                                                _error_occurred = true;
+                                                None // 读取错误，返回None
+                                            },
+                                        }
+                                    } => {
+                                        if let Some(result) = result {
+                                            pending.extend_from_slice(&result);
+                                            // 以'\n'分割，打印每条消息
+                                            while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+                                                let line = String::from_utf8_lossy(&pending[..pos]).to_string();
+                                                pending.drain(..=pos);
+                                                if debug && !line.is_empty() {
+                                                    println!("Received for {}: {}", wav_file, line);
+                                                }
+                                                if let Some(event) = TranscriptEvent::parse_line(&line) {
+                                                    transcript.push(event);
+                                                } else if let Some(error) = ServerError::parse_line(&line) {
+                                                    _running_result = RunningResult::ServerError(error.code);
+                                                    _error_occurred = true;
+                                                }
+                                            }
+                                        } else {
+                                            break; // 没有数据可读，连接可能已经关闭
                                         }
-                                        Some(String::from_utf8_lossy(&buf[..n]).to_string())
-                                    },
-                                    Err(_) => {
-                                        _running_result = RunningResult::ReadFailed;
-                                        _error_occurred = true;
-                                        return None; // 读取错误，返回None
                                     },
+                                    _ = sleep(total_timeout) => {
+                                        if debug {
+                                            println!("Reading from server timeout occurred");
+                                        }
+                                        break;
+                                    }
                                 }
-                            } => {
-                                if let Some(result) = result {
-                                    // 以'\n'分割，打印每条消息
-                                    for line in result.split('\n') {
-                                        if !line.is_empty() {
+                            }
+                        },
+                        ProtocolMode::Framed => {
+                            // 读取服务器响应，直到收到 End 帧或服务端关闭连接
+                            loop {
+                                tokio::select! {
+                                    result = read_frame(&mut stream, &mut decoder) => match result {
+                                        Ok(Some(Frame::Result(line))) => {
                                             if debug {
                                                 println!("Received for {}: {}", wav_file, line);
                                             }
-                                            _transcribe_result = line.to_string();
+                                            if let Some(event) = TranscriptEvent::parse_line(&line) {
+                                                transcript.push(event);
+                                            }
+                                        },
+                                        Ok(Some(Frame::Error(error))) => {
+                                            if debug {
+                                                println!("Server error for {}: {}", wav_file, error);
+                                            }
+                                            _running_result = RunningResult::ServerError(error.code);
+                                            _error_occurred = true;
+                                        },
+                                        Ok(Some(Frame::End)) => break,
+                                        Ok(Some(frame)) => {
+                                            if debug {
+                                                println!("Ignoring unexpected {} frame", frame.name());
+                                            }
+                                        },
+                                        Ok(None) => {
+                                            // 没有收到 End 帧就被关闭，说明服务端没有处理完这次请求
+                                            if _running_result == RunningResult::Succeccess {
+                                                _running_result = RunningResult::ReadEof;
+                                            }
+                                            break;
+                                        },
+                                        Err(_) => {
+                                            _running_result = RunningResult::ReadFailed;
+                                            _error_occurred = true;
+                                            break;
+                                        },
+                                    },
+                                    _ = sleep(total_timeout) => {
+                                        if debug {
+                                            println!("Reading from server timeout occurred");
                                         }
+                                        break;
                                     }
-                                } else {
-                                    break; // 没有数据可读，连接可能已经关闭
                                 }
-                            },
-                            _ = sleep(timeout_duration) => {
-                                if debug {
-                                    println!("Reading from server timeout occurred");
-                                }
-                                break;
                             }
-                        }
+                        },
                     }
                     let receiving_time = start_time.elapsed().as_nanos() as usize;
-                    // 主动关闭连接
-                    tokio::select! {
-                        _ = stream.shutdown() => {},
-                        _ = sleep(timeout_duration) => {},
-
-                    };
 
                     if debug {
                         println!("Connection closed.");
                     }
-                    Ok(RunningRecord::new(wav_file, _running_result, _error_occurred, readfile_time, connecting_time, sending_time, receiving_time, _transcribe_result))
+                    Ok(RunningRecord::new(wav_file, _running_result, _error_occurred, readfile_time, connecting_time, sending_time, receiving_time, transcript.text()))
                 }, // 连接成功，直接返回
                 Err(_) => Ok(RunningRecord::new(wav_file, RunningResult::ConnectFailed, true, 0, 0, 0, 0, "".to_string())), // 连接失败，返回错误
             }
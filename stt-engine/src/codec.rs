@@ -0,0 +1,178 @@
+use std::io::Cursor;
+
+use symphonia::core::{audio::SampleBuffer, codecs::{DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS}, errors::Error, formats::{FormatOptions, FormatReader, Packet}, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
+
+use crate::{audio::{downmix, SampleFormat, StreamResampler}, wav};
+
+// 压缩音频需要收完整个文件才能解码，单个会话最多缓存这么多字节
+pub const MAX_COMPRESSED_SIZE: usize = 256 << 20;
+
+/// 目前没有可用的纯 Rust Opus 解码器，Ogg/Opus 用这个错误拒绝
+pub const OPUS_UNSUPPORTED: &str = "Ogg/Opus audio is not supported, convert it to FLAC, MP3, Ogg/Vorbis or WAV";
+
+// Ogg/Opus 的 OpusHead 在第一个页面中，只在文件开头这么多字节里查找
+const OPUS_HEAD_WINDOW: usize = 64;
+
+/// 解码后的音频，`samples` 是按声道交错排列的 f32 采样
+#[derive(Debug, Clone)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl DecodedAudio {
+    /// 混合成单声道并重采样到 `sample_rate`
+    pub fn to_mono(&self, sample_rate: u32) -> Result<Vec<f32>, String> {
+        let samples = if self.channels > 1 {
+            downmix(&self.samples, self.channels)
+        } else {
+            self.samples.clone()
+        };
+        let mut resampler = StreamResampler::new(self.sample_rate, sample_rate)?;
+        let mut output = resampler.process(&samples)?;
+        output.extend(resampler.flush()?);
+        Ok(output)
+    }
+}
+
+/// 解码完整的音频文件，支持 WAV、FLAC、MP3 和 Ogg/Vorbis。
+///
+/// 压缩格式由 symphonia 解码；Ogg/Opus 能识别出来，但会返回 [`OPUS_UNSUPPORTED`]。
+pub fn decode(bytes: Vec<u8>) -> Result<DecodedAudio, String> {
+    if bytes.starts_with(b"RIFF") {
+        let wav = wav::parse(&bytes)?;
+        let format = SampleFormat::from_wav(&wav.format)?;
+        // 截断的文件最后可能不足一帧
+        let frame_size = format.bytes_per_sample() * wav.format.channels as usize;
+        let len = wav.data.len() - wav.data.len() % frame_size;
+        return Ok(DecodedAudio {
+            sample_rate: wav.format.sample_rate,
+            channels: wav.format.channels as usize,
+            samples: format.decode(&wav.data[..len]),
+        });
+    }
+
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&Hint::new(), source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported audio format: {}", e))?;
+    let mut reader = probed.format;
+    let track = reader.tracks().iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found".to_string())?;
+    if track.codec_params.codec == CODEC_TYPE_OPUS {
+        return Err(OPUS_UNSUPPORTED.to_string());
+    }
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut channels = track.codec_params.channels.map(|channels| channels.count());
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported audio codec: {}", e))?;
+
+    let mut samples = Vec::new();
+    while let Some(packet) = next_packet(reader.as_mut())? {
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(buffer) => {
+                let spec = *buffer.spec();
+                sample_rate = Some(spec.rate);
+                channels = Some(spec.channels.count());
+                let mut interleaved = SampleBuffer::<f32>::new(buffer.capacity() as u64, spec);
+                interleaved.copy_interleaved_ref(buffer);
+                samples.extend_from_slice(interleaved.samples());
+            },
+            // 个别损坏的数据包跳过，不影响后面的解码
+            Err(Error::DecodeError(e)) => {
+                eprintln!("Skipping undecodable packet: {}", e);
+            },
+            Err(e) => return Err(format!("Error decoding audio: {}", e)),
+        }
+    }
+
+    match (sample_rate, channels) {
+        (Some(sample_rate), Some(channels)) if sample_rate > 0 && channels > 0 => Ok(DecodedAudio { sample_rate, channels, samples }),
+        _ => Err("Unknown sample rate or channel count".to_string()),
+    }
+}
+
+/// 文件开头是否是 Ogg/Opus，收到完整文件之前就可以拒绝
+pub fn is_opus(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"OggS") && bytes[..bytes.len().min(OPUS_HEAD_WINDOW)].windows(8).any(|window| window == b"OpusHead")
+}
+
+// 读到文件末尾时返回 `None`
+fn next_packet(reader: &mut dyn FormatReader) -> Result<Option<Packet>, String> {
+    match reader.next_packet() {
+        Ok(packet) => Ok(Some(packet)),
+        Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(format!("Error reading audio: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ogg 页面的 CRC，多项式 0x04c11db7，不反转
+    fn crc32(bytes: &[u8]) -> u32 {
+        bytes.iter().fold(0u32, |crc, byte| {
+            (0..8).fold(crc ^ ((*byte as u32) << 24), |crc, _| if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 })
+        })
+    }
+
+    // 每个页面只放一个数据包
+    fn ogg_page(packet: &[u8], granule: u64, sequence: u32, header_type: u8) -> Vec<u8> {
+        let mut lacing = vec![255; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&1u32.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(packet);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    fn ogg_opus() -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, 1]);
+        head.extend_from_slice(&312u16.to_le_bytes());
+        head.extend_from_slice(&16000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&4u32.to_le_bytes());
+        tags.extend_from_slice(b"test");
+        tags.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut bytes = ogg_page(&head, 0, 0, 0x02);
+        bytes.extend(ogg_page(&tags, 0, 1, 0));
+        // 20ms 的静音帧
+        for i in 0..10 {
+            let header_type = if i == 9 { 0x04 } else { 0 };
+            bytes.extend(ogg_page(&[0xf8, 0xff, 0xfe], (i as u64 + 1) * 960, i + 2, header_type));
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_ogg_opus() {
+        let bytes = ogg_opus();
+        assert!(is_opus(&bytes));
+        assert!(!is_opus(&bytes[..30]));
+        assert_eq!(decode(bytes).unwrap_err(), OPUS_UNSUPPORTED);
+
+        let mut vorbis = b"\x01vorbis".to_vec();
+        vorbis.extend_from_slice(&[0; 22]);
+        assert!(!is_opus(&ogg_page(&vorbis, 0, 0, 0x02)));
+    }
+}
@@ -18,368 +18,1379 @@ pub trait Executor {
 trait Channel {
     type Message;
 
-    fn open(&mut self, capacity: usize) -> impl Future<Output = ()>;
-    fn close(&mut self) -> impl Future<Output = ()>;
+    async fn open(&mut self, capacity: usize) -> Result<(), String>;
 }
 
 trait Sender<M> {
-    fn send(&self, message: M) -> impl Future<Output = ()>;
-}
-
-trait Receiver<M> {
-    fn recv(&self, _message: M) -> ();
+    async fn send(&self, message: M);
 }
 
 pub mod server {
-    use std::{future::Future, sync::{atomic::AtomicBool, Arc}, time::Duration};
+    use std::{net::SocketAddr, path::{Path, PathBuf}, sync::{atomic::AtomicBool, Arc}, time::Duration};
 
-    use derive_new::new;
-    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{tcp::OwnedWriteHalf, TcpListener, TcpStream}, signal::ctrl_c, sync::mpsc, time::sleep};
+    use futures_util::{stream::{SplitSink, SplitStream}, SinkExt, StreamExt};
+    use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpListener, TcpStream, UnixListener}, signal::ctrl_c, sync::{mpsc, OwnedSemaphorePermit, Semaphore}, time::{sleep, timeout}};
+    use tokio_rustls::TlsAcceptor;
+    use tokio_stream::wrappers::ReceiverStream;
+    use tokio_tungstenite::{accept_hdr_async, tungstenite::{self, handshake::server::{Request as UpgradeRequest, Response as UpgradeResponse}, http::HeaderValue, protocol::{frame::coding::CloseCode, CloseFrame}, Message}, WebSocketStream};
+    use tonic::{Status, Streaming};
 
-    use crate::sherpa::Sherpa;
+    use crate::{audio::{AudioConfig, AudioPipeline, SampleFormat}, auth::{self, Authenticator, Lease}, codec, pool::Pool, grpc::{self, proto::{speech_recognizer_server::{SpeechRecognizer, SpeechRecognizerServer}, streaming_recognize_request, RecognizeRequest, RecognizeResponse, StreamingRecognizeRequest, StreamingRecognizeResponse}, GrpcSink}, http::{self, read_head, Api, HttpError, Job, Responder}, protocol::{accept_session, read_frame, write_frame, ErrorCode, Frame, FrameDecoder, ProtocolMode, ServerError, SessionConfig, SessionDefaults, StartConfig}, scheduler::{DecodeScheduler, DecodeSession, SchedulerConfig}, sherpa::{ModelConfig, Recognizer}, tls::ServerTlsConfig, transcript::{ResultFormat, Segmenter, TranscriptEvent}, wav::AudioContainer};
 
     use super::{Channel, Endpoint, Executor, Sender};
 
-    pub(crate) const SHERPA_TOKENS: &str = "../sherpa/sherpa-models/tokens.txt";
-    pub(crate) const SHERPA_ENCODER: &str = "../sherpa/sherpa-models/encoder-epoch-20-avg-1-chunk-16-left-128.onnx";
-    pub(crate) const SHERPA_DECODER: &str = "../sherpa/sherpa-models/decoder-epoch-20-avg-1-chunk-16-left-128.onnx";
-    pub(crate) const SHERPA_JOINER: &str = "../sherpa/sherpa-models/joiner-epoch-20-avg-1-chunk-16-left-128.onnx";
-
-    #[derive(Debug, Clone, new)]
+    /// 服务端配置，没有列出的字段使用默认值，例如
+    /// `ServerConfig { port: 0, channel_num: 2, ..Default::default() }`。
+    #[derive(Debug, Clone)]
     pub struct ServerConfig {
-        ip: &'static str,
-        port: u16,
-        channel_num: usize,
-        channel_capacity: usize,
-        retry_support: bool,
-        retry_max: usize,
-        retry_interval: usize,
-        read_timeout: usize,
+        pub ip: &'static str,
+        pub port: u16,
+        // 设置后在这个端口上同时监听 WebSocket 连接
+        pub websocket_port: Option<u16>,
+        // 设置后在这个端口上同时提供 HTTP 接口
+        pub http_port: Option<u16>,
+        // HTTP 请求体的字节数上限
+        pub http_max_body: usize,
+        // 设置后在这个端口上同时提供 gRPC 服务
+        pub grpc_port: Option<u16>,
+        // 设置后同时在这个 Unix domain socket 上监听，协议与 TCP 监听相同
+        pub unix_socket: Option<UnixSocketConfig>,
+        // 设置后 TCP 监听只接受 TLS 连接，其他监听不受影响
+        pub tls: Option<ServerTlsConfig>,
+        // 设置后按这个密钥文件认证客户端，并限制每个密钥的并发会话数和每日音频时长
+        pub auth_keys: Option<PathBuf>,
+        pub channel_num: usize,
+        pub channel_capacity: usize,
+        // 没有空闲通道时排队等待的最长秒数，为 0 时直接返回繁忙
+        pub max_wait: usize,
+        pub read_timeout: usize,
+        pub protocol: ProtocolMode,
+        pub result_format: ResultFormat,
+        pub audio_config: AudioConfig,
+        pub scheduler_config: SchedulerConfig,
+        pub model_config: ModelConfig,
     }
 
-    pub enum ServerMessage {
-        Connected(OwnedWriteHalf),
-        Disconnected,
-        DataReceived(Vec<u8>),
-        CloseChannel,
+    impl Default for ServerConfig {
+        fn default() -> Self {
+            Self {
+                ip: "0.0.0.0",
+                port: 8888,
+                websocket_port: None,
+                http_port: None,
+                http_max_body: http::DEFAULT_MAX_BODY,
+                grpc_port: None,
+                unix_socket: None,
+                tls: None,
+                auth_keys: None,
+                channel_num: 20,
+                channel_capacity: 20,
+                max_wait: 0,
+                read_timeout: 2,
+                protocol: ProtocolMode::default(),
+                result_format: ResultFormat::default(),
+                audio_config: AudioConfig::default(),
+                scheduler_config: SchedulerConfig::default(),
+                model_config: ModelConfig::default(),
+            }
+        }
     }
 
-    #[derive(Debug)]
-    struct TcpStreamChannel {
-        sherpa_proxy: Option<Arc<Sherpa>>,
-        sender: Option<mpsc::Sender<ServerMessage>>,
-        onwed_writer: Option<OwnedWriteHalf>,
-        is_selected: Arc<AtomicBool>,
-        is_client_closed: Arc<AtomicBool>,
+    /// Unix domain socket 监听的路径和文件权限，权限决定哪些用户可以连接
+    #[derive(Debug, Clone)]
+    pub struct UnixSocketConfig {
+        pub path: PathBuf,
+        pub mode: u32,
     }
 
-    impl TcpStreamChannel {
-        fn is_selected(&self) -> bool {
-            self.is_selected.load(std::sync::atomic::Ordering::Relaxed)
+    impl UnixSocketConfig {
+        // 默认只允许同一用户和用户组连接
+        const DEFAULT_MODE: u32 = 0o660;
+
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self { path: path.into(), mode: Self::DEFAULT_MODE }
         }
 
-        fn select(&self) -> () {
-            self.is_selected.store(true, std::sync::atomic::Ordering::Relaxed)
+        /// 读取 `STT_UNIX_SOCKET` 和八进制的 `STT_UNIX_SOCKET_MODE`（默认 660），没有设置路径时返回 `None`
+        pub fn from_env() -> Result<Option<Self>, String> {
+            let Ok(path) = std::env::var("STT_UNIX_SOCKET") else {
+                return Ok(None);
+            };
+            let mut config = Self::new(path);
+            if let Ok(mode) = std::env::var("STT_UNIX_SOCKET_MODE") {
+                config.mode = u32::from_str_radix(&mode, 8)
+                    .map_err(|_| format!("Invalid value for STT_UNIX_SOCKET_MODE: {}", mode))?;
+            }
+            Ok(Some(config))
         }
 
-        fn set_client_closed(&self, client_closed: bool) -> () {
-            self.is_client_closed.store(client_closed, std::sync::atomic::Ordering::Relaxed)
+        // 上次异常退出会留下 socket 文件，确认没有服务在监听后再删除
+        fn bind(&self) -> std::io::Result<UnixListener> {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+            if std::fs::symlink_metadata(&self.path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                if std::os::unix::net::UnixStream::connect(&self.path).is_ok() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, "Another server is listening on this socket"));
+                }
+                std::fs::remove_file(&self.path)?;
+            }
+            let listener = UnixListener::bind(&self.path)?;
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(self.mode))?;
+            Ok(listener)
         }
     }
 
-    impl Default for TcpStreamChannel {
-        fn default() -> Self {
+    pub enum ServerMessage {
+        // 带上会话 ID 和本次会话的配置，分帧协议下配置由客户端的 Start 帧决定；开启认证时带上会话占用的名额
+        Connected(SessionWriter, String, SessionConfig, Option<Lease>),
+        // 客户端发送完音频，输出最后的结果后再关闭连接
+        InputFinished,
+        Disconnected,
+        // 客户端发送了不合协议的数据或者超时，通知客户端后断开
+        Failed(ServerError),
+        DataReceived(Vec<u8>),
+        CloseChannel,
+    }
+
+    struct TcpStreamChannel<R: Recognizer> {
+        recognizer: Arc<R>,
+        scheduler: Arc<DecodeScheduler<R>>,
+        audio_config: AudioConfig,
+        protocol: ProtocolMode,
+        sherpa_proxy: Option<Arc<DecodeSession<R>>>,
+        sender: Option<mpsc::Sender<ServerMessage>>,
+        onwed_writer: Option<SessionWriter>,
+        // 通道在池中的编号，会话结束后归还
+        pool: Arc<Pool>,
+        slot: usize,
+        is_client_closed: Arc<AtomicBool>,
+    }
+
+    impl<R: Recognizer> TcpStreamChannel<R> {
+        fn new(recognizer: Arc<R>, scheduler: Arc<DecodeScheduler<R>>, audio_config: AudioConfig, protocol: ProtocolMode,
+            pool: Arc<Pool>, slot: usize) -> Self {
             Self {
+                recognizer,
+                scheduler,
+                audio_config,
+                protocol,
                 sherpa_proxy: None,
                 sender: None,
                 onwed_writer: None,
-                is_selected: Arc::new(false.into()),
+                pool,
+                slot,
                 is_client_closed: Arc::new(true.into()),
             }
         }
+
+        fn set_client_closed(&self, client_closed: bool) {
+            self.is_client_closed.store(client_closed, std::sync::atomic::Ordering::Relaxed)
+        }
     }
 
-    impl Channel for TcpStreamChannel {
+    impl<R: Recognizer> Channel for TcpStreamChannel<R> {
         type Message = ServerMessage;
         
-        fn open(&mut self, capacity: usize) -> impl Future<Output = ()> {
-            async move {
-                let mut sherpa_proxy = Sherpa::new();
-                sherpa_proxy.init(SHERPA_TOKENS, SHERPA_ENCODER, SHERPA_DECODER, SHERPA_JOINER);
-                self.sherpa_proxy.replace(Arc::new(sherpa_proxy));
-
-                let (tx, mut rx) = mpsc::channel::<ServerMessage>(capacity);
-                self.sender = Some(tx);
-
-                {
-                    let mut onwed_writer = self.onwed_writer.take();
-                    let is_selected = self.is_selected.clone();
-                    let is_client_closed = self.is_client_closed.clone();
-                    let sherpa_proxy = self.sherpa_proxy.as_ref().unwrap().clone();
-                    tokio::spawn(async move {
-                        while let Some(message) = rx.recv().await {
-                            match message {
-                                ServerMessage::Connected(writer) => {
-                                    onwed_writer.replace(writer);
-                                },
-                                ServerMessage::Disconnected => {
-                                    match sherpa_proxy.reset() {
-                                        Ok(_) => {
-                                            println!("Sharpa proxy reset successfully");
-                                            is_selected.store(false, std::sync::atomic::Ordering::Relaxed);
-                                            is_client_closed.store(false, std::sync::atomic::Ordering::Relaxed);
+        async fn open(&mut self, capacity: usize) -> Result<(), String> {
+            let recognizer = self.recognizer.clone();
+            let stream = tokio::task::spawn_blocking(move || R::create_stream(&recognizer)).await
+                .map_err(|e| e.to_string())??;
+            self.sherpa_proxy.replace(Arc::new(self.scheduler.session(Arc::new(stream))));
+
+            let (tx, mut rx) = mpsc::channel::<ServerMessage>(capacity);
+            self.sender = Some(tx);
+
+            {
+                let mut onwed_writer = self.onwed_writer.take();
+                let mut lease: Option<Lease> = None;
+                let pool = self.pool.clone();
+                let slot = self.slot;
+                let is_client_closed = self.is_client_closed.clone();
+                let recognizer = self.recognizer.clone();
+                let scheduler = self.scheduler.clone();
+                // 第一个识别流随通道创建，多声道分别识别时其余声道的识别流在用到时才创建，每个会话结束后都换成新的识别流
+                let mut tracks = vec![Track {
+                    session: self.sherpa_proxy.as_ref().unwrap().clone(),
+                    segmenter: Segmenter::with_channel(self.audio_config.channel_label(0)),
+                }];
+                // 输入音频统一转换成识别流的采样率
+                let mut pipeline = AudioPipeline::new(self.audio_config.clone(), recognizer.sample_rate());
+                let mut output = Output {
+                    protocol: self.protocol,
+                    format: ResultFormat::default(),
+                    session_id: String::new(),
+                };
+                tokio::spawn(async move {
+                    while let Some(message) = rx.recv().await {
+                        match message {
+                            ServerMessage::Connected(writer, session_id, config, session_lease) => {
+                                onwed_writer.replace(writer);
+                                lease = session_lease;
+                                if let Some(lease) = &lease {
+                                    println!("Session {} authenticated with key {}", session_id, lease.key_id());
+                                }
+                                for (i, track) in tracks.iter_mut().enumerate() {
+                                    track.segmenter = Segmenter::with_channel(config.audio_config.channel_label(i));
+                                }
+                                pipeline = AudioPipeline::new(config.audio_config, recognizer.sample_rate());
+                                output.format = config.result_format;
+                                output.session_id = session_id;
+                            },
+                            ServerMessage::InputFinished => {
+                                // 重采样器中还有缓存的采样，先送去识别；压缩音频在这里才解码，放到阻塞线程中执行
+                                let audio_config = pipeline.config().clone();
+                                let tails = match tokio::task::spawn_blocking(move || {
+                                    let tails = pipeline.finish();
+                                    (pipeline, tails)
+                                }).await {
+                                    Ok((finished, tails)) => {
+                                        pipeline = finished;
+                                        tails
+                                    },
+                                    Err(e) => {
+                                        pipeline = AudioPipeline::new(audio_config.clone(), recognizer.sample_rate());
+                                        Err(e.to_string())
+                                    },
+                                };
+                                // 压缩音频解码后才知道声道数
+                                let tails = match tails {
+                                    Ok(tails) => add_tracks(&mut tracks, tails.len(), &recognizer, &scheduler, &audio_config).await
+                                        .map(|_| tails)
+                                        .map_err(|e| ServerError::new(ErrorCode::RecognizerError, e)),
+                                    Err(e) => Err(audio_error(e)),
+                                };
+                                // 压缩音频解码后才知道时长，先计入用量，超过当天配额时和流式音频一样不再识别
+                                let tails = tails.and_then(|tails| match &lease {
+                                    Some(lease) => lease.record(duration(&tails, recognizer.sample_rate())).map(|_| tails),
+                                    None => Ok(tails),
+                                });
+                                match tails {
+                                    Ok(tails) => {
+                                        for (track, tail) in tracks.iter_mut().zip(tails) {
+                                            if tail.is_empty() {
+                                                continue;
+                                            }
+                                            track.segmenter.advance(tail.len(), recognizer.sample_rate());
+                                            match track.session.transcribe(tail).await {
+                                                Ok(result) => {
+                                                    if let (Some(writer), Some(event)) = (&mut onwed_writer, track.segmenter.push(&result)) {
+                                                        if let Err(e) = writer.write_event(&output, &event).await {
+                                                            eprintln!("Error writing result: {}", e);
+                                                        }
+                                                    }
+                                                },
+                                                Err(e) => {
+                                                    eprintln!("Error transcribing: {}", e);
+                                                    if let Some(writer) = &mut onwed_writer {
+                                                        let _ = writer.write_error(&output, &ServerError::new(ErrorCode::RecognizerError, e)).await;
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    },
+                                    Err(e) => {
+                                        eprintln!("Error flushing audio: {}", e);
+                                        if let Some(writer) = &mut onwed_writer {
+                                            let _ = writer.write_error(&output, &e).await;
+                                        }
+                                    }
+                                }
+                                let mut finished = true;
+                                for track in tracks.iter_mut() {
+                                    match track.session.finish().await {
+                                        Ok(result) => {
+                                            if let (Some(writer), Some(event)) = (&mut onwed_writer, track.segmenter.push(&result)) {
+                                                if let Err(e) = writer.write_event(&output, &event).await {
+                                                    eprintln!("Error writing final result: {}", e);
+                                                }
+                                            }
                                         },
                                         Err(e) => {
-                                            eprintln!("Error resetting sherpa proxy: {}", e);
+                                            eprintln!("Error finishing sherpa proxy: {}", e);
+                                            if let Some(writer) = &mut onwed_writer {
+                                                let _ = writer.write_error(&output, &ServerError::new(ErrorCode::RecognizerError, e)).await;
+                                            }
+                                            finished = false;
                                         }
                                     }
-                                },
-                                ServerMessage::DataReceived(data) => {
-                                    if is_client_closed.load(std::sync::atomic::Ordering::Relaxed) {
-                                        eprintln!("Client is closed, cannot receive data");
-                                    } else {
-                                        if let Some(writer) = &mut onwed_writer {
-                                            if data.len() < 2 {
-                                                eprintln!("Invalid data length: {}", data.len());
+                                }
+                                // 会话已经结束，识别流没有收尾成功也释放名额和通道
+                                lease.take();
+                                if let Some(writer) = &mut onwed_writer {
+                                    let closed = if finished { writer.finish(&output).await } else { writer.shutdown().await };
+                                    if let Err(e) = closed {
+                                        eprintln!("Error closing stream: {}", e);
+                                    }
+                                }
+                                if finished {
+                                    println!("Sharpa proxy finished successfully");
+                                }
+                                renew_tracks(&mut tracks, &recognizer, &scheduler).await;
+                                onwed_writer.take();
+                                is_client_closed.store(false, std::sync::atomic::Ordering::Relaxed);
+                                pool.release(slot);
+                            },
+                            ServerMessage::Failed(e) => {
+                                eprintln!("Session failed: {}", e);
+                                if let Some(writer) = &mut onwed_writer {
+                                    let _ = writer.write_error(&output, &e).await;
+                                    let _ = writer.shutdown().await;
+                                }
+                                lease.take();
+                                renew_tracks(&mut tracks, &recognizer, &scheduler).await;
+                                onwed_writer.take();
+                                is_client_closed.store(false, std::sync::atomic::Ordering::Relaxed);
+                                pool.release(slot);
+                            },
+                            ServerMessage::Disconnected => {
+                                lease.take();
+                                renew_tracks(&mut tracks, &recognizer, &scheduler).await;
+                                onwed_writer.take();
+                                is_client_closed.store(false, std::sync::atomic::Ordering::Relaxed);
+                                pool.release(slot);
+                            },
+                            ServerMessage::DataReceived(data) => {
+                                if is_client_closed.load(std::sync::atomic::Ordering::Relaxed) {
+                                    eprintln!("Client is closed, cannot receive data");
+                                } else {
+                                    // 容器头、跨读取边界的半个采样、声道和重采样都在 pipeline 中处理
+                                    let outputs = match pipeline.push(&data) {
+                                        Ok(outputs) if outputs.iter().all(Vec::is_empty) => continue,
+                                        Ok(outputs) => add_tracks(&mut tracks, outputs.len(), &recognizer, &scheduler, pipeline.config()).await
+                                            .map(|_| outputs)
+                                            .map_err(|e| ServerError::new(ErrorCode::RecognizerError, e)),
+                                        Err(e) => Err(audio_error(e)),
+                                    };
+                                    // 超过当天配额时通知客户端并结束会话
+                                    let outputs = outputs.and_then(|outputs| match &lease {
+                                        Some(lease) => lease.record(duration(&outputs, recognizer.sample_rate())).map(|_| outputs),
+                                        None => Ok(outputs),
+                                    });
+                                    let outputs = match outputs {
+                                        Ok(outputs) => outputs,
+                                        Err(e) => {
+                                            eprintln!("{}", e);
+                                            abort(onwed_writer.as_mut(), &output, &is_client_closed, &e).await;
+                                            continue;
+                                        },
+                                    };
+                                    if let Some(writer) = &mut onwed_writer {
+                                        for (track, sample) in tracks.iter_mut().zip(outputs) {
+                                            if sample.is_empty() {
                                                 continue;
-                                            } else {
-                                                // data两两一组，每组数据转成f32，然后把这些f32数据收集起来组成一个Vec<f32>
-                                                let sample = data.chunks(2).map(|chunk| {
-                                                    ((chunk[1] as i16) << 8 | (chunk[0] as i16) & 0xff) as f32 / 32767f32
-                                                }).collect::<Vec<f32>>();
-                                                match sherpa_proxy.transcribe(&sample) {
-                                                    Ok(result) => {
-                                                        if result != "" {
-                                                            let result = format!("{}\n", result);
-                                                            println!("Transcribed: {}", result);
-                                                            // if let Err(e) = writer.write_all(result.as_bytes()).await {
-                                                            //     eprintln!("Error writing to stream: {}", e);
-                                                            // }
-                                                            match writer.write_all(result.as_bytes()).await {
-                                                                Err(e) => {
-                                                                    if e.kind() == tokio::io::ErrorKind::BrokenPipe {
-                                                                        eprintln!("Client is closed due to: {}", e);
-                                                                        is_client_closed.store(true, std::sync::atomic::Ordering::Relaxed);
-                                                                    }
-                                                                },
-                                                                _ => {},
+                                            }
+                                            track.segmenter.advance(sample.len(), recognizer.sample_rate());
+                                            match track.session.transcribe(sample).await {
+                                                Ok(result) => {
+                                                    // 未变化的 partial 不再重复发送
+                                                    if let Some(event) = track.segmenter.push(&result) {
+                                                        if let Err(e) = writer.write_event(&output, &event).await {
+                                                            if e.kind() == tokio::io::ErrorKind::BrokenPipe {
+                                                                eprintln!("Client is closed due to: {}", e);
+                                                                is_client_closed.store(true, std::sync::atomic::Ordering::Relaxed);
+                                                            } else {
+                                                                eprintln!("Error writing result: {}", e);
                                                             }
-                                                            writer.flush().await.unwrap();
                                                         }
-                                                    },
-                                                    Err(e) => {
-                                                        eprintln!("Error transcribing: {}", e);
                                                     }
+                                                },
+                                                Err(e) => {
+                                                    eprintln!("Error transcribing: {}", e);
+                                                    abort(Some(writer), &output, &is_client_closed, &ServerError::new(ErrorCode::RecognizerError, e)).await;
+                                                    break;
                                                 }
                                             }
                                         }
                                     }
-                                },
-                                ServerMessage::CloseChannel => {
-                                    break;
-                                },
-                            }
+                                }
+                            },
+                            ServerMessage::CloseChannel => {
+                                break;
+                            },
                         }
-                    });
-                }
+                    }
+                });
             }
+            Ok(())
         }
-        
-        fn close(&mut self) -> impl Future<Output = ()> {
-            async move {
-                if let Some(sender) = &self.sender {
-                    sender.send(ServerMessage::Disconnected).await.unwrap();
+    }
+
+    // 一个识别流和它的分句状态，多声道分别识别时每个声道一个
+    struct Track<R: Recognizer> {
+        session: Arc<DecodeSession<R>>,
+        segmenter: Segmenter,
+    }
+
+    async fn add_tracks<R: Recognizer>(tracks: &mut Vec<Track<R>>, count: usize, recognizer: &Arc<R>,
+        scheduler: &DecodeScheduler<R>, audio_config: &AudioConfig) -> Result<(), String> {
+        while tracks.len() < count {
+            let recognizer = recognizer.clone();
+            let stream = tokio::task::spawn_blocking(move || R::create_stream(&recognizer)).await
+                .map_err(|e| e.to_string())??;
+            tracks.push(Track {
+                session: Arc::new(scheduler.session(Arc::new(stream))),
+                segmenter: Segmenter::with_channel(audio_config.channel_label(tracks.len())),
+            });
+        }
+        Ok(())
+    }
+
+    // 不支持的编码是请求的问题，其他错误是音频数据本身的问题
+    fn audio_error(e: String) -> ServerError {
+        if e == codec::OPUS_UNSUPPORTED {
+            ServerError::new(ErrorCode::InvalidRequest, e)
+        } else {
+            ServerError::new(ErrorCode::InvalidAudio, format!("Invalid audio data: {}", e))
+        }
+    }
+
+    // 各声道的采样数相同，按最长的一个计算时长
+    fn duration(samples: &[Vec<f32>], sample_rate: u32) -> f64 {
+        samples.iter().map(Vec::len).max().unwrap_or_default() as f64 / sample_rate as f64
+    }
+
+    // 识别流结束输入后不能再接受音频，会话结束后为每个声道换上新建的识别流；
+    // 新建失败时去掉这个及之后的声道，下次用到时由 add_tracks 重新创建
+    async fn renew_tracks<R: Recognizer>(tracks: &mut Vec<Track<R>>, recognizer: &Arc<R>, scheduler: &DecodeScheduler<R>) {
+        for i in 0..tracks.len() {
+            let recognizer = recognizer.clone();
+            let stream = tokio::task::spawn_blocking(move || R::create_stream(&recognizer)).await
+                .map_err(|e| e.to_string())
+                .and_then(|stream| stream);
+            match stream {
+                Ok(stream) => tracks[i].session = Arc::new(scheduler.session(Arc::new(stream))),
+                Err(e) => {
+                    eprintln!("Error recreating sherpa stream: {}", e);
+                    tracks.truncate(i);
+                    return;
                 }
-                if let Some(sherpa_proxy) = &mut self.sherpa_proxy {
-                    if let Err(e) = sherpa_proxy.close() {
-                        eprintln!("Error closing sherpa proxy: {}", e);
+            }
+        }
+    }
+
+    // 当前会话的协议、结果格式和会话 ID，决定识别消息怎样写给客户端
+    struct Output {
+        protocol: ProtocolMode,
+        format: ResultFormat,
+        session_id: String,
+    }
+
+    impl Output {
+        async fn write_event<W: AsyncWrite + Unpin>(&self, writer: &mut W, event: &TranscriptEvent) -> std::io::Result<()> {
+            let line = event.to_format(self.format, &self.session_id);
+            println!("Transcribed: {}", line.trim_end());
+            match self.protocol {
+                ProtocolMode::Raw => {
+                    writer.write_all(line.as_bytes()).await?;
+                    writer.flush().await
+                },
+                ProtocolMode::Framed => write_frame(writer, &Frame::Result(line.trim_end().to_string())).await,
+            }
+        }
+
+        // 裸协议下错误消息也占一行，格式与识别消息一致
+        async fn write_error<W: AsyncWrite + Unpin>(&self, writer: &mut W, error: &ServerError) -> std::io::Result<()> {
+            match self.protocol {
+                ProtocolMode::Raw => {
+                    writer.write_all(error.to_format(self.format, &self.session_id).as_bytes()).await?;
+                    writer.flush().await
+                },
+                ProtocolMode::Framed => write_frame(writer, &Frame::Error(error.clone())).await,
+            }
+        }
+    }
+
+    type WebSocketSink = SplitSink<WebSocketStream<TcpStream>, Message>;
+
+    // HTTP 和 gRPC 一次上传的完整音频按这个大小分块送去识别，和流式连接一样逐块输出中间结果
+    const UPLOAD_CHUNK_SIZE: usize = 32000;
+
+    // gRPC 响应流中等待发送的结果数
+    const GRPC_RESPONSE_CAPACITY: usize = 32;
+
+    // 会话的写端，TCP 和 Unix domain socket 连接按 `Output` 的协议写，WebSocket 连接按 sherpa-onnx 的 websocket 服务的格式写，
+    // HTTP 请求在识别完成后一次性写出响应，gRPC 请求把结果发送给响应流
+    pub enum SessionWriter {
+        Stream(Box<dyn AsyncWrite + Send + Sync + Unpin>),
+        WebSocket(WebSocketSink),
+        Http(Responder<TcpStream>),
+        Grpc(GrpcSink),
+    }
+
+    impl SessionWriter {
+        async fn write_event(&mut self, output: &Output, event: &TranscriptEvent) -> std::io::Result<()> {
+            match self {
+                Self::Stream(writer) => output.write_event(writer, event).await,
+                Self::WebSocket(sink) => {
+                    let json = event.to_sherpa_json();
+                    println!("Transcribed: {}", json);
+                    sink.send(Message::text(json)).await.map_err(websocket_error)
+                },
+                Self::Http(responder) => {
+                    println!("Transcribed: {}", event.to_line().trim_end());
+                    responder.push(event);
+                    Ok(())
+                },
+                Self::Grpc(sink) => {
+                    println!("Transcribed: {}", event.to_line().trim_end());
+                    sink.send_event(event).await
+                },
+            }
+        }
+
+        // sherpa-onnx 没有定义错误消息，WebSocket 连接使用 JSON 结果格式的错误消息
+        async fn write_error(&mut self, output: &Output, error: &ServerError) -> std::io::Result<()> {
+            match self {
+                Self::Stream(writer) => output.write_error(writer, error).await,
+                Self::WebSocket(sink) => {
+                    let json = error.to_json(&output.session_id);
+                    sink.send(Message::text(json.trim_end())).await.map_err(websocket_error)
+                },
+                Self::Http(responder) => responder.respond_error(error).await,
+                Self::Grpc(sink) => sink.send_error(error).await,
+            }
+        }
+
+        // 识别完成后关闭连接：分帧协议先写 End 帧，WebSocket 与 sherpa-onnx 一样以 "Done" 为原因关闭
+        async fn finish(&mut self, output: &Output) -> std::io::Result<()> {
+            match self {
+                Self::Stream(writer) => {
+                    if output.protocol == ProtocolMode::Framed {
+                        write_frame(writer, &Frame::End).await?;
                     }
+                    writer.shutdown().await
+                },
+                Self::WebSocket(sink) => {
+                    let frame = CloseFrame { code: CloseCode::Normal, reason: "Done".into() };
+                    sink.send(Message::Close(Some(frame))).await.map_err(websocket_error)
+                },
+                Self::Http(responder) => {
+                    responder.respond().await?;
+                    responder.shutdown().await
+                },
+                // 会话结束后写端被丢弃，响应流随之正常结束
+                Self::Grpc(_) => Ok(()),
+            }
+        }
+
+        async fn shutdown(&mut self) -> std::io::Result<()> {
+            match self {
+                Self::Stream(writer) => writer.shutdown().await,
+                Self::WebSocket(sink) => sink.close().await.map_err(websocket_error),
+                Self::Http(responder) => responder.shutdown().await,
+                Self::Grpc(_) => Ok(()),
+            }
+        }
+    }
+
+    // 浏览器中的客户端不能设置请求头，凭证也可以作为子协议 `bearer` 之后的一项发送，例如 `Sec-WebSocket-Protocol: bearer, <credential>`；
+    // 不从查询参数中读取，避免凭证出现在 URL 和访问日志中
+    fn websocket_credential(request: &UpgradeRequest) -> (Option<String>, bool) {
+        let authorization = request.headers().get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(auth::bearer);
+        let protocol = request.headers().get("sec-websocket-protocol")
+            .and_then(|value| value.to_str().ok())
+            .and_then(auth::bearer_protocol);
+        let via_protocol = authorization.is_none() && protocol.is_some();
+        (authorization.or(protocol).map(str::to_string), via_protocol)
+    }
+
+    // 发送错误消息后关闭连接，等客户端回应关闭帧，避免客户端读不到错误消息
+    async fn reject_websocket(mut writer: WebSocketSink, mut reader: SplitStream<WebSocketStream<TcpStream>>, error: &ServerError,
+        read_timeout: usize) {
+        let _ = writer.send(Message::text(error.to_json("").trim_end())).await;
+        let _ = writer.close().await;
+        let _ = timeout(Duration::from_secs(read_timeout as u64), async {
+            while let Some(Ok(_)) = reader.next().await {}
+        }).await;
+    }
+
+    // 连接已关闭的错误统一成 BrokenPipe，和 TCP 连接的处理一致
+    fn websocket_error(e: tungstenite::Error) -> std::io::Error {
+        match e {
+            tungstenite::Error::Io(e) => e,
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => std::io::ErrorKind::BrokenPipe.into(),
+            e => std::io::Error::other(e),
+        }
+    }
+
+    // 通知客户端出错并关闭写端，之后收到的数据都丢弃
+    async fn abort(writer: Option<&mut SessionWriter>, output: &Output, is_client_closed: &AtomicBool, error: &ServerError) {
+        is_client_closed.store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Some(writer) = writer {
+            let _ = writer.write_error(output, error).await;
+            let _ = writer.shutdown().await;
+        }
+    }
+
+    // 写完错误消息后关闭写端，丢弃客户端还在发送的数据直到对方关闭或超时，
+    // 避免直接关闭连接时客户端收到 RST，读不到错误消息
+    async fn linger<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, read_timeout: usize) {
+        if stream.shutdown().await.is_err() {
+            return;
+        }
+        let mut buf = [0; 4096];
+        let _ = timeout(Duration::from_secs(read_timeout as u64), async {
+            while let Ok(n) = stream.read(&mut buf).await {
+                if n == 0 {
+                    break;
                 }
             }
+        }).await;
+    }
+
+    fn timeout_error(read_timeout: usize) -> ServerError {
+        ServerError::new(ErrorCode::Timeout, format!("No data received from client in {}s", read_timeout))
+    }
+
+    // 裸协议：读到多少转发多少，客户端关闭写端表示音频发送完毕
+    async fn read_raw<R: Recognizer, T: AsyncRead + Unpin>(reader: &mut T, channel: &TcpStreamChannel<R>, read_timeout: usize) -> ServerMessage {
+        let mut buf = [0; 4096];
+        loop {
+            tokio::select! {
+                result = reader.read(&mut buf) => match result {
+                    // 结尾不足一整块的数据也不会丢失
+                    Ok(n) if n > 0 => channel.send(ServerMessage::DataReceived(buf[..n].to_vec())).await,
+                    // 客户端关闭了写端
+                    Ok(_) => return ServerMessage::InputFinished,
+                    Err(e) => {
+                        eprintln!("Error: Reading from stream failed: {}", e);
+                        return ServerMessage::Disconnected;
+                    },
+                },
+                _ = sleep(Duration::from_secs(read_timeout as u64)) => {
+                    // println!("Reading from client timeout occurred");
+                    return ServerMessage::Failed(timeout_error(read_timeout));
+                }
+            }
+        }
+    }
+
+    // 分帧协议：转发 Audio 帧直到收到 End 帧，没有 End 帧就关闭连接视为中断
+    async fn read_framed<R: Recognizer, T: AsyncRead + Unpin>(reader: &mut T, mut decoder: FrameDecoder, channel: &TcpStreamChannel<R>,
+        read_timeout: usize) -> ServerMessage {
+        loop {
+            tokio::select! {
+                result = read_frame(reader, &mut decoder) => match result {
+                    Ok(Some(Frame::Audio(data))) => channel.send(ServerMessage::DataReceived(data)).await,
+                    Ok(Some(Frame::End)) => return ServerMessage::InputFinished,
+                    Ok(Some(frame)) => return ServerMessage::Failed(ServerError::new(ErrorCode::InvalidRequest, format!("Unexpected {} frame", frame.name()))),
+                    Ok(None) => return ServerMessage::Disconnected,
+                    Err(e) => return ServerMessage::Failed(ServerError::new(ErrorCode::InvalidRequest, e)),
+                },
+                _ = sleep(Duration::from_secs(read_timeout as u64)) => {
+                    return ServerMessage::Failed(timeout_error(read_timeout));
+                }
+            }
+        }
+    }
+
+    // WebSocket 连接：二进制消息是 f32 采样，文本消息 "Done" 表示音频发送完毕
+    async fn read_websocket<R: Recognizer>(reader: &mut SplitStream<WebSocketStream<TcpStream>>, channel: &TcpStreamChannel<R>,
+        read_timeout: usize) -> ServerMessage {
+        loop {
+            tokio::select! {
+                message = reader.next() => match message {
+                    Some(Ok(Message::Binary(data))) => channel.send(ServerMessage::DataReceived(data.to_vec())).await,
+                    Some(Ok(Message::Text(text))) if text.as_str() == "Done" => return ServerMessage::InputFinished,
+                    Some(Ok(Message::Text(text))) => return ServerMessage::Failed(ServerError::new(ErrorCode::InvalidRequest, format!("Unexpected text message: {}", text.as_str()))),
+                    // Ping 和 Pong 由 tungstenite 处理
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {},
+                    Some(Ok(Message::Close(_))) | None => return ServerMessage::Disconnected,
+                    Some(Err(e)) => {
+                        eprintln!("Error: Reading from websocket failed: {}", e);
+                        return ServerMessage::Disconnected;
+                    },
+                },
+                _ = sleep(Duration::from_secs(read_timeout as u64)) => {
+                    return ServerMessage::Failed(timeout_error(read_timeout));
+                }
+            }
+        }
+    }
+
+    // gRPC 流：转发音频直到客户端关闭发送端，配置只能在第一个消息中发送
+    async fn read_grpc<R: Recognizer>(reader: &mut Streaming<StreamingRecognizeRequest>, channel: &TcpStreamChannel<R>,
+        read_timeout: usize) -> ServerMessage {
+        loop {
+            tokio::select! {
+                message = reader.message() => match message {
+                    Ok(Some(StreamingRecognizeRequest { request: Some(streaming_recognize_request::Request::Audio(data)) })) => {
+                        channel.send(ServerMessage::DataReceived(data)).await
+                    },
+                    Ok(Some(_)) => return ServerMessage::Failed(ServerError::new(ErrorCode::InvalidRequest, "Config must be the first message")),
+                    Ok(None) => return ServerMessage::InputFinished,
+                    Err(e) => {
+                        eprintln!("Error: Reading from grpc stream failed: {}", e);
+                        return ServerMessage::Disconnected;
+                    },
+                },
+                _ = sleep(Duration::from_secs(read_timeout as u64)) => {
+                    return ServerMessage::Failed(timeout_error(read_timeout));
+                }
+            }
+        }
+    }
+
+    // gRPC 服务，和 TCP 监听共用同一组通道
+    struct GrpcService<R: Recognizer> {
+        channels: Arc<ChannelPool<R>>,
+        read_timeout: usize,
+        defaults: Arc<SessionDefaults>,
+        auth: Option<Arc<Authenticator>>,
+    }
+
+    impl<R: Recognizer> GrpcService<R> {
+        // 凭证在 `authorization` 元数据中，格式为 `Bearer <credential>`
+        fn credential<T>(request: &tonic::Request<T>) -> Option<String> {
+            request.metadata().get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(auth::bearer)
+                .map(str::to_string)
+        }
+
+        // 认证并分配通道，开始会话，返回通道、会话 ID 和结果的接收端
+        async fn connect(&self, config: SessionConfig, credential: Option<&str>) -> Result<(Arc<TcpStreamChannel<R>>, String,
+            mpsc::Receiver<Result<StreamingRecognizeResponse, Status>>), Status> {
+            let lease = authorize(self.auth.as_ref(), credential).map_err(|e| grpc::status(&e))?;
+            let channel = self.channels.acquire().await
+                .ok_or_else(|| grpc::status(&ServerError::new(ErrorCode::Busy, "No channel available")))?;
+            let session_id = uuid::Uuid::new_v4().to_string();
+            println!("gRPC session {}", session_id);
+            let (sender, receiver) = mpsc::channel(GRPC_RESPONSE_CAPACITY);
+            let writer = SessionWriter::Grpc(GrpcSink::new(sender, session_id.clone()));
+            channel.send(ServerMessage::Connected(writer, session_id.clone(), config, lease)).await;
+            Ok((channel, session_id, receiver))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl<R: Recognizer> SpeechRecognizer for GrpcService<R> {
+        type StreamingRecognizeStream = ReceiverStream<Result<StreamingRecognizeResponse, Status>>;
+
+        async fn streaming_recognize(&self, request: tonic::Request<Streaming<StreamingRecognizeRequest>>)
+            -> Result<tonic::Response<Self::StreamingRecognizeStream>, Status> {
+            let credential = Self::credential(&request);
+            let mut reader = request.into_inner();
+            let read_timeout = self.read_timeout;
+            // 第一个消息可以是配置，也可以直接是音频
+            let first = timeout(Duration::from_secs(read_timeout as u64), reader.message()).await
+                .map_err(|_| grpc::status(&timeout_error(read_timeout)))??;
+            let (start, audio) = match first.and_then(|message| message.request) {
+                Some(streaming_recognize_request::Request::Config(config)) => (grpc::start_config(config)?, None),
+                Some(streaming_recognize_request::Request::Audio(audio)) => (StartConfig::default(), Some(audio)),
+                None => (StartConfig::default(), None),
+            };
+            let config = start.session_config(&self.defaults).map_err(|e| grpc::status(&e))?;
+            let (channel, _, receiver) = self.connect(config, credential.as_deref()).await?;
+            tokio::spawn(async move {
+                if let Some(audio) = audio {
+                    channel.send(ServerMessage::DataReceived(audio)).await;
+                }
+                let message = read_grpc(&mut reader, &channel, read_timeout).await;
+                channel.send(message).await;
+            });
+            Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+        }
+
+        async fn recognize(&self, request: tonic::Request<RecognizeRequest>) -> Result<tonic::Response<RecognizeResponse>, Status> {
+            let credential = Self::credential(&request);
+            let RecognizeRequest { config, audio } = request.into_inner();
+            let mut start = grpc::start_config(config.unwrap_or_default())?;
+            // 没有指定容器时按文件头判断，都不是则按裸 PCM 处理
+            if start.container.is_none() {
+                start.container = http::detect_container(&audio);
+            }
+            if start.container == Some(AudioContainer::Compressed) && codec::is_opus(&audio) {
+                return Err(grpc::status(&ServerError::new(ErrorCode::InvalidRequest, codec::OPUS_UNSUPPORTED)));
+            }
+            let config = start.session_config(&self.defaults).map_err(|e| grpc::status(&e))?;
+            let (channel, session_id, mut receiver) = self.connect(config, credential.as_deref()).await?;
+            // 边发送音频边读取结果，否则结果堆满响应通道后通道任务停下，发送也会一直等待
+            let send = async {
+                for data in audio.chunks(UPLOAD_CHUNK_SIZE) {
+                    channel.send(ServerMessage::DataReceived(data.to_vec())).await;
+                }
+                channel.send(ServerMessage::InputFinished).await;
+            };
+            let receive = async {
+                let mut segments = Vec::new();
+                while let Some(response) = receiver.recv().await {
+                    if let Some(result) = response?.result.filter(|result| result.is_final) {
+                        segments.push(result);
+                    }
+                }
+                Ok::<_, Status>(segments)
+            };
+            let (_, segments) = tokio::join!(send, receive);
+            let segments = segments?;
+            let text = segments.iter().map(|result| result.text.as_str()).collect::<Vec<_>>().join(" ");
+            Ok(tonic::Response::new(RecognizeResponse { session_id, text, segments }))
         }
     }
 
-    impl Sender<ServerMessage> for TcpStreamChannel {
-        fn send(&self, message: ServerMessage) -> impl Future<Output = ()> {
-            async move {
-                if let Some(sender) = &self.sender {
-                    sender.send(message).await.unwrap();
+    // 处理一个 TCP 或 Unix domain socket 连接，两种监听使用相同的协议和通道
+    #[allow(clippy::too_many_arguments)]
+    async fn serve_stream<R: Recognizer, S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static>(mut stream: S, peer: String,
+        channels: Arc<ChannelPool<R>>, read_timeout: usize, protocol: ProtocolMode, defaults: Arc<SessionDefaults>, auth: Option<Arc<Authenticator>>) {
+        let mut decoder = FrameDecoder::default();
+        // 握手在分配通道之前完成，版本或参数不对的连接不会占用通道
+        // 开启认证时服务端只接受分帧协议，裸协议没有凭证
+        let (config, lease) = match protocol {
+            ProtocolMode::Raw => (SessionConfig {
+                audio_config: defaults.audio_config.clone(),
+                result_format: defaults.result_format,
+            }, None),
+            ProtocolMode::Framed => {
+                let handshake = timeout(Duration::from_secs(read_timeout as u64), accept_session(&mut stream, &mut decoder, &defaults)).await
+                    .unwrap_or_else(|_| Err(timeout_error(read_timeout)))
+                    .and_then(|(config, credential)| Ok((config, authorize(auth.as_ref(), credential.as_deref())?)));
+                match handshake {
+                    Ok(handshake) => handshake,
+                    Err(e) => {
+                        eprintln!("Handshake failed: {}", e);
+                        let _ = write_frame(&mut stream, &Frame::Error(e)).await;
+                        linger(stream, read_timeout).await;
+                        return;
+                    },
+                }
+            },
+        };
+        match channels.acquire().await {
+            Some(channel) => {
+                let session_id = uuid::Uuid::new_v4().to_string();
+                println!("Connected: {}, session {}", peer, session_id);
+                if protocol == ProtocolMode::Framed {
+                    // 写失败时读取端也会出错，由读取任务释放通道
+                    if let Err(e) = write_frame(&mut stream, &Frame::Ready(session_id.clone())).await {
+                        eprintln!("Error writing ready frame: {}", e);
+                    }
                 }
+                let (mut reader, writer) = tokio::io::split(stream);
+                channel.send(ServerMessage::Connected(SessionWriter::Stream(Box::new(writer)), session_id, config, lease)).await;
+
+                tokio::spawn(async move {
+                    let message = match protocol {
+                        ProtocolMode::Raw => read_raw(&mut reader, &channel, read_timeout).await,
+                        ProtocolMode::Framed => read_framed(&mut reader, decoder, &channel, read_timeout).await,
+                    };
+                    channel.send(message).await;
+                });
+            },
+            None => {
+                // 通知客户端服务端繁忙，客户端可以稍后重试
+                let output = Output {
+                    protocol,
+                    format: config.result_format,
+                    session_id: String::new(),
+                };
+                let _ = output.write_error(&mut stream, &ServerError::new(ErrorCode::Busy, "No channel available")).await;
+                linger(stream, read_timeout).await;
+            },
+        }
+    }
+
+    // 没有空闲名额时最多等待 `max_wait`，为 0 时不等待
+    async fn acquire_permit(semaphore: Arc<Semaphore>, max_wait: Duration) -> Option<OwnedSemaphorePermit> {
+        match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) if max_wait.is_zero() => None,
+            Err(_) => timeout(max_wait, semaphore.acquire_owned()).await.ok()?.ok(),
+        }
+    }
+
+    // 认证在分配通道之前完成，没有开启认证时不限制
+    fn authorize(auth: Option<&Arc<Authenticator>>, credential: Option<&str>) -> Result<Option<Lease>, ServerError> {
+        auth.map(|auth| auth.admit(credential)).transpose()
+    }
+
+    // 所有监听共用的一组通道，会话结束时通道自己归还到池中
+    struct ChannelPool<R: Recognizer> {
+        channels: Vec<Arc<TcpStreamChannel<R>>>,
+        pool: Arc<Pool>,
+        max_wait: Duration,
+    }
+
+    impl<R: Recognizer> ChannelPool<R> {
+        // 取出一个空闲的通道，没有时按先来先到排队，最多等待 `max_wait`
+        async fn acquire(&self) -> Option<Arc<TcpStreamChannel<R>>> {
+            let Some(slot) = self.pool.acquire(self.max_wait).await else {
+                eprintln!("No channel available after waiting {}s", self.max_wait.as_secs());
+                return None;
+            };
+            let channel = self.channels[slot].clone();
+            channel.set_client_closed(false);
+            Some(channel)
+        }
+    }
+
+    impl<R: Recognizer> Sender<ServerMessage> for TcpStreamChannel<R> {
+        async fn send(&self, message: ServerMessage) {
+            if let Some(sender) = &self.sender {
+                sender.send(message).await.unwrap();
             }
         }
     }
 
-    struct TcpListenerExecutor {
+    struct TcpListenerExecutor<R: Recognizer> {
         listener: Option<TcpListener>,
-        channels: Arc<Vec<Arc<TcpStreamChannel>>>,
-        retry_support: bool,
-        retry_max: usize,
-        retry_interval: usize,
+        websocket_listener: Option<TcpListener>,
+        http_listener: Option<TcpListener>,
+        grpc_listener: Option<TcpListener>,
+        unix_listener: Option<UnixListener>,
+        tls_acceptor: Option<TlsAcceptor>,
+        // WebSocket 连接没有握手参数，总是使用这个配置
+        websocket_config: SessionConfig,
+        channels: Arc<ChannelPool<R>>,
+        // 同时在读取请求体的 HTTP 请求数不超过通道数，请求体都缓存在内存中
+        http_requests: Arc<Semaphore>,
+        http_max_body: usize,
         read_timeout: usize,
+        protocol: ProtocolMode,
+        defaults: Arc<SessionDefaults>,
+        auth: Option<Arc<Authenticator>>,
     }
 
-    impl TcpListenerExecutor {
-        fn build_from(listener: TcpListener, num: usize, capacity: usize, retry_support: bool,
-            retry_max: usize, retry_interval: usize, read_timeout: usize) -> impl Future<Output = Self> {
+    impl<R: Recognizer> TcpListenerExecutor<R> {
+        #[allow(clippy::too_many_arguments)]
+        async fn build_from(listener: TcpListener, websocket_listener: Option<TcpListener>, http_listener: Option<TcpListener>,
+            grpc_listener: Option<TcpListener>, unix_listener: Option<UnixListener>, tls_acceptor: Option<TlsAcceptor>, websocket_config: SessionConfig, num: usize, capacity: usize,
+            max_wait: usize, http_max_body: usize, read_timeout: usize, recognizer: Arc<R>,
+            scheduler: Arc<DecodeScheduler<R>>, protocol: ProtocolMode, defaults: SessionDefaults, auth: Option<Authenticator>) -> Self {
             let mut channels = Vec::new();
-            async move {
-                for _ in 0..num {
-                    let mut channel = TcpStreamChannel::default();
-                    channel.open(capacity).await;
-                    channels.push(Arc::new(channel));
+            let pool = Arc::new(Pool::new());
+            // 所有通道共享同一个识别器，每个通道只创建自己的识别流
+            for i in 0..num {
+                let mut channel = TcpStreamChannel::new(recognizer.clone(), scheduler.clone(), defaults.audio_config.clone(), protocol,
+                    pool.clone(), channels.len());
+                match channel.open(capacity).await {
+                    Ok(_) => {
+                        // 打开成功后才加入池中
+                        pool.release(channels.len());
+                        channels.push(Arc::new(channel));
+                    },
+                    Err(e) => eprintln!("Failed to open channel {}: {}", i, e),
                 }
+            }
     
-                Self {
-                    listener: Some(listener),
-                    channels: Arc::new(channels),
-                    retry_support,
-                    retry_max,
-                    retry_interval,
-                    read_timeout
+            Self {
+                listener: Some(listener),
+                websocket_listener,
+                http_listener,
+                grpc_listener,
+                unix_listener,
+                tls_acceptor,
+                websocket_config,
+                http_requests: Arc::new(Semaphore::new(channels.len())),
+                http_max_body,
+                channels: Arc::new(ChannelPool {
+                    channels,
+                    pool,
+                    max_wait: Duration::from_secs(max_wait as u64),
+                }),
+                read_timeout,
+                protocol,
+                defaults: Arc::new(defaults),
+                auth: auth.map(Arc::new),
+            }
+        }
+
+        // 与 sherpa-onnx 的 websocket 服务兼容，它的客户端不用修改就能连接
+        #[allow(clippy::result_large_err)]
+        async fn accept_websocket(&self) {
+            let Some(listener) = &self.websocket_listener else {
+                // 没有开启 WebSocket 时不影响 TCP 监听
+                return std::future::pending().await;
+            };
+            loop {
+                if let Ok((stream, addr)) = listener.accept().await {
+                    let channels = self.channels.clone();
+                    let read_timeout = self.read_timeout;
+                    let config = self.websocket_config.clone();
+                    let auth = self.auth.clone();
+
+                    tokio::spawn(async move {
+                        let mut credential = None;
+                        let upgrade = accept_hdr_async(stream, |request: &UpgradeRequest, mut response: UpgradeResponse| {
+                            let via_protocol;
+                            (credential, via_protocol) = websocket_credential(request);
+                            // 客户端请求了子协议时必须选中一个，否则浏览器会断开连接
+                            if via_protocol {
+                                response.headers_mut().insert("sec-websocket-protocol", HeaderValue::from_static(auth::BEARER_PROTOCOL));
+                            }
+                            Ok(response)
+                        });
+                        let websocket = match timeout(Duration::from_secs(read_timeout as u64), upgrade).await {
+                            Ok(Ok(websocket)) => websocket,
+                            Ok(Err(e)) => {
+                                eprintln!("WebSocket handshake failed: {}", e);
+                                return;
+                            },
+                            Err(_) => {
+                                eprintln!("WebSocket handshake timed out");
+                                return;
+                            },
+                        };
+                        let (writer, mut reader) = websocket.split();
+                        let lease = match authorize(auth.as_ref(), credential.as_deref()) {
+                            Ok(lease) => lease,
+                            Err(e) => {
+                                eprintln!("WebSocket rejected: {}", e);
+                                return reject_websocket(writer, reader, &e, read_timeout).await;
+                            },
+                        };
+                        match channels.acquire().await {
+                            Some(channel) => {
+                                let session_id = uuid::Uuid::new_v4().to_string();
+                                println!("WebSocket connected: {}, session {}", addr, session_id);
+                                channel.send(ServerMessage::Connected(SessionWriter::WebSocket(writer), session_id, config, lease)).await;
+
+                                tokio::spawn(async move {
+                                    let message = read_websocket(&mut reader, &channel, read_timeout).await;
+                                    channel.send(message).await;
+                                });
+                            },
+                            None => {
+                                let error = ServerError::new(ErrorCode::Busy, "No channel available");
+                                reject_websocket(writer, reader, &error, read_timeout).await;
+                            },
+                        }
+                    });
+                }
+            }
+        }
+
+        async fn accept_unix(&self) {
+            let Some(listener) = &self.unix_listener else {
+                return std::future::pending().await;
+            };
+            loop {
+                if let Ok((stream, _)) = listener.accept().await {
+                    // Unix domain socket 的对端没有地址，日志中记录对端进程
+                    let peer = match stream.peer_cred() {
+                        Ok(cred) => format!("unix uid {} pid {}", cred.uid(), cred.pid().unwrap_or_default()),
+                        Err(_) => "unix".to_string(),
+                    };
+                    tokio::spawn(serve_stream(stream, peer, self.channels.clone(), self.read_timeout, self.protocol,
+                        self.defaults.clone(), self.auth.clone()));
+                }
+            }
+        }
+
+        async fn accept_grpc(&self) {
+            let Some(listener) = &self.grpc_listener else {
+                return std::future::pending().await;
+            };
+            let service = GrpcService {
+                channels: self.channels.clone(),
+                read_timeout: self.read_timeout,
+                defaults: self.defaults.clone(),
+                auth: self.auth.clone(),
+            };
+            let incoming = futures_util::stream::unfold(listener, |listener| async move {
+                Some((listener.accept().await.map(|(stream, _)| stream), listener))
+            });
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(SpeechRecognizerServer::new(service))
+                .serve_with_incoming(incoming).await {
+                eprintln!("gRPC server failed: {}", e);
+            }
+        }
+
+        // 每个连接处理一个请求，收完请求体后才分配通道，上传慢的客户端不会占用通道；
+        // 请求头读完就认证，同时缓存请求体的请求数不超过通道数，没有名额时和通道一样最多等待 `max_wait`
+        async fn accept_http(&self) {
+            let Some(listener) = &self.http_listener else {
+                return std::future::pending().await;
+            };
+            loop {
+                if let Ok((stream, addr)) = listener.accept().await {
+                    let channels = self.channels.clone();
+                    let requests = self.http_requests.clone();
+                    let max_body = self.http_max_body;
+                    let read_timeout = self.read_timeout;
+                    let defaults = self.defaults.clone();
+                    let auth = self.auth.clone();
+
+                    tokio::spawn(async move {
+                        let mut stream = stream;
+                        let Some(_permit) = acquire_permit(requests, channels.max_wait).await else {
+                            eprintln!("HTTP request rejected: too many requests in progress");
+                            let error = HttpError::new(503, ErrorCode::Busy, "Too many requests in progress");
+                            let _ = http::write_error(&mut stream, &Api::Transcribe, &error, "").await;
+                            linger(stream, read_timeout).await;
+                            return;
+                        };
+                        let read_timeout_duration = Duration::from_secs(read_timeout as u64);
+                        let head = read_head(&mut stream, read_timeout_duration).await.map_err(|e| (Api::Transcribe, e));
+                        let head = head.and_then(|head| match authorize(auth.as_ref(), head.credential()) {
+                            Ok(lease) => Ok((head, lease)),
+                            Err(e) => Err((head.api(), e.into())),
+                        });
+                        let job = match head {
+                            Ok((head, lease)) => {
+                                let api = head.api();
+                                head.read_body(&mut stream, max_body, read_timeout_duration).await
+                                    .map_err(|e| (api, e))
+                                    .and_then(|request| http::route(request, &defaults))
+                                    .map(|job| (job, lease))
+                            },
+                            Err(e) => Err(e),
+                        };
+                        let (Job { api, audio, config }, lease) = match job {
+                            Ok(job) => job,
+                            Err((api, e)) => {
+                                eprintln!("HTTP request failed: {}", e.error);
+                                let _ = http::write_error(&mut stream, &api, &e, "").await;
+                                linger(stream, read_timeout).await;
+                                return;
+                            },
+                        };
+                        match channels.acquire().await {
+                            Some(channel) => {
+                                let session_id = uuid::Uuid::new_v4().to_string();
+                                println!("HTTP request: {}, session {}", addr, session_id);
+                                let responder = Responder::new(stream, api, session_id.clone());
+                                channel.send(ServerMessage::Connected(SessionWriter::Http(responder), session_id, config, lease)).await;
+                                for data in audio.chunks(UPLOAD_CHUNK_SIZE) {
+                                    channel.send(ServerMessage::DataReceived(data.to_vec())).await;
+                                }
+                                channel.send(ServerMessage::InputFinished).await;
+                            },
+                            None => {
+                                let error = HttpError::new(503, ErrorCode::Busy, "No channel available");
+                                let _ = http::write_error(&mut stream, &api, &error, "").await;
+                                linger(stream, read_timeout).await;
+                            },
+                        }
+                    });
                 }
             }
         }
     }
     
-    impl Executor for TcpListenerExecutor {
+    impl<R: Recognizer> Executor for TcpListenerExecutor<R> {
         type Context = TcpStream;
-        type Channel = TcpStreamChannel;
+        type Channel = TcpStreamChannel<R>;
     
-        fn execute(&self) -> impl Future<Output = ()> {
-            async move {
-                tokio::select! {
-                    _ = async {
-                        if let Some(listener) = &self.listener {
-                            loop {
-                                if let Ok((stream, _)) = listener.accept().await {
-                                    {
-                                        let channels = self.channels.clone();
-                                        let retry_support = self.retry_support;
-                                        let retry_max = self.retry_max;
-                                        let retry_interval = self.retry_interval;
-                                        let read_timeout = self.read_timeout;
-                                        
-                                        tokio::spawn(async move {
-                                            let mut channel = None;
-                                            if retry_support {
-                                                let mut retrying_count = 0;
-                                                loop {
-                                                    if retrying_count >= retry_max {
-                                                        eprintln!("Failed to select a channel after {} attempts", retry_max);
-                                                        break; // 达到最大尝试次数后退出循环
-                                                    }
-                                                    if let Some(ch) = channels.iter().find(|c| !c.is_selected()) {
-                                                        ch.select();
-                                                        ch.set_client_closed(false);
-                                                        channel.replace(Arc::clone(ch));
-                                                        break;
-                                                    } else {
-                                                        retrying_count += 1;
-                                                        eprintln!("No channel available for selection, retrying count: {}", retrying_count);
-                                                        sleep(Duration::from_secs(retry_interval as u64)).await;
-                                                    }
-                                                }
-                                            } else {
-                                                if let Some(ch) = channels.iter().find(|c| !c.is_selected()) {
-                                                    ch.select();
-                                                    channel.replace(Arc::clone(ch));
-                                                }
-                                            }
-                                            match channel {
-                                                Some(channel) => {
-                                                    // handle(stream, channel.clone()).await;
-                                                    let channel = channel.clone();
-                                                    let addr = stream.peer_addr().unwrap();
-                                                    println!("Connected: {}", addr);
-                                                    let (mut reader, writer) = stream.into_split();
-                                                    channel.send(ServerMessage::Connected(writer)).await;
-
-                                                    tokio::spawn(async move {
-                                                        // println!("Reader start...");
-                                                        let mut buf = [0; 4096];
-
-                                                        loop {
-                                                            tokio::select! {
-                                                                result = async {
-                                                                    match reader.read_exact(&mut buf).await {
-                                                                        Ok(n) => {
-                                                                            if n == 0 {
-                                                                                Err("End of stream")
-                                                                            } else {
-                                                                                Ok(buf[..n].to_vec())
-                                                                            }
-                                                                        },
-                                                                        Err(_) => Err("Reading from stream failed"),
-                                                                    }
-                                                                } => match result {
-                                                                    Ok(data) => channel.send(ServerMessage::DataReceived(data)).await,
-                                                                    Err(e) => {
-                                                                        eprintln!("Error: {}", e);
-                                                                        break;
-                                                                    },
-                                                                },
-                                                                _ = sleep(Duration::from_secs(read_timeout as u64)) => {
-                                                                    // println!("Reading from client timeout occurred");
-                                                                    break;
-                                                                }
-                                                            }
-                                                        }
-                                                        channel.send(ServerMessage::Disconnected).await;
-                                                    });
-                                                },
-                                                None => {
-                                                    // eprintln!("No channel available, closed stream...");
-                                                    drop(stream);
-                                                },
-                                            }
-                                        });
-                                    }
+        async fn execute(&self) {
+            tokio::select! {
+                _ = async {
+                    if let Some(listener) = &self.listener {
+                        loop {
+                            if let Ok((stream, addr)) = listener.accept().await {
+                                {
+                                    let channels = self.channels.clone();
+                                    let read_timeout = self.read_timeout;
+                                    let protocol = self.protocol;
+                                    let defaults = self.defaults.clone();
+                                    let tls_acceptor = self.tls_acceptor.clone();
+                                    let auth = self.auth.clone();
+
+                                    tokio::spawn(async move {
+                                        let Some(acceptor) = tls_acceptor else {
+                                            return serve_stream(stream, addr.to_string(), channels, read_timeout, protocol,
+                                                defaults, auth).await;
+                                        };
+                                        // TLS 握手和协议握手一样受读超时限制，握手失败时直接断开
+                                        match timeout(Duration::from_secs(read_timeout as u64), acceptor.accept(stream)).await {
+                                            Ok(Ok(stream)) => serve_stream(stream, addr.to_string(), channels, read_timeout, protocol,
+                                                defaults, auth).await,
+                                            Ok(Err(e)) => eprintln!("TLS handshake with {} failed: {}", addr, e),
+                                            Err(_) => eprintln!("TLS handshake with {} timed out", addr),
+                                        }
+                                    });
                                 }
                             }
                         }
-                    } => {},
-                    _ = ctrl_c() => {}
-                }
-                println!("\nServer is shutting down...");
-                for channel in self.channels.iter() {
-                    channel.send(ServerMessage::CloseChannel).await;
-                }
+                    }
+                } => {},
+                _ = self.accept_websocket() => {},
+                _ = self.accept_http() => {},
+                _ = self.accept_grpc() => {},
+                _ = self.accept_unix() => {},
+                _ = ctrl_c() => {}
+            }
+            println!("\nServer is shutting down...");
+            for channel in self.channels.channels.iter() {
+                channel.send(ServerMessage::CloseChannel).await;
+            }
+            if let Some(path) = self.unix_listener.as_ref().and_then(|listener| listener.local_addr().ok())
+                .and_then(|addr| addr.as_pathname().map(Path::to_path_buf)) {
+                let _ = std::fs::remove_file(path);
             }
         }
     }
 
-    pub struct Server {
-        executor: TcpListenerExecutor,
+    pub struct Server<R: Recognizer> {
+        recognizer: Arc<R>,
+        executor: TcpListenerExecutor<R>,
     }
 
-    impl Endpoint for Server {
+    impl<R: Recognizer> Server<R> {
+        pub fn recognizer(&self) -> Arc<R> {
+            self.recognizer.clone()
+        }
+
+        pub fn local_addr(&self) -> Option<SocketAddr> {
+            self.executor.listener.as_ref().and_then(|listener| listener.local_addr().ok())
+        }
+
+        pub fn websocket_addr(&self) -> Option<SocketAddr> {
+            self.executor.websocket_listener.as_ref().and_then(|listener| listener.local_addr().ok())
+        }
+
+        pub fn http_addr(&self) -> Option<SocketAddr> {
+            self.executor.http_listener.as_ref().and_then(|listener| listener.local_addr().ok())
+        }
+
+        pub fn grpc_addr(&self) -> Option<SocketAddr> {
+            self.executor.grpc_listener.as_ref().and_then(|listener| listener.local_addr().ok())
+        }
+
+        pub fn unix_socket_path(&self) -> Option<PathBuf> {
+            self.executor.unix_listener.as_ref().and_then(|listener| listener.local_addr().ok())
+                .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+        }
+    }
+
+    impl<R: Recognizer> Endpoint for Server<R> {
         type Config = ServerConfig;
         type Output = Self;
 
-        fn init(config: Self::Config) -> impl Future<Output = Option<Self::Output>> {
+        async fn init(config: Self::Config) -> Option<Self::Output> {
             let addr = format!("{}:{}", config.ip, config.port);
-            async move {
-                if let Ok(listener) = TcpListener::bind(addr).await {
-                    let executor = TcpListenerExecutor::build_from(listener,
-                        config.channel_num,
-                        config.channel_capacity,
-                        config.retry_support,
-                        config.retry_max,
-                        config.retry_interval,
-                        config.read_timeout).await;
-                    Some(Self {
-                        executor,
-                    })
-                } else {
-                    None
+            // 加载模型比较耗时，放到阻塞线程池中执行
+            let model_config = config.model_config.clone();
+            let recognizer = tokio::task::spawn_blocking(move || R::create(&model_config)).await
+                .map_err(|e| e.to_string())
+                .and_then(|recognizer| recognizer);
+            let recognizer = match recognizer {
+                Ok(recognizer) => Arc::new(recognizer),
+                Err(e) => {
+                    eprintln!("Failed to create recognizer: {}", e);
+                    return None;
                 }
+            };
+            let scheduler = match DecodeScheduler::start(recognizer.clone(), &config.scheduler_config) {
+                Ok(scheduler) => Arc::new(scheduler),
+                Err(e) => {
+                    eprintln!("Failed to start decode scheduler: {}", e);
+                    return None;
+                }
+            };
+            let websocket_listener = match config.websocket_port {
+                Some(port) => match TcpListener::bind(format!("{}:{}", config.ip, port)).await {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        eprintln!("Failed to bind websocket port {}: {}", port, e);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            let http_listener = match config.http_port {
+                Some(port) => match TcpListener::bind(format!("{}:{}", config.ip, port)).await {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        eprintln!("Failed to bind http port {}: {}", port, e);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            let grpc_listener = match config.grpc_port {
+                Some(port) => match TcpListener::bind(format!("{}:{}", config.ip, port)).await {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        eprintln!("Failed to bind grpc port {}: {}", port, e);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            let unix_listener = match &config.unix_socket {
+                Some(unix_socket) => match unix_socket.bind() {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        eprintln!("Failed to bind unix socket {}: {}", unix_socket.path.display(), e);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            let tls_acceptor = match &config.tls {
+                Some(tls) => match tls.acceptor() {
+                    Ok(acceptor) => Some(acceptor),
+                    Err(e) => {
+                        eprintln!("Failed to load TLS config: {}", e);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            // 裸协议没有握手，无法携带凭证
+            let auth = match &config.auth_keys {
+                Some(_) if config.protocol == ProtocolMode::Raw => {
+                    eprintln!("Authentication requires the framed protocol");
+                    return None;
+                },
+                Some(path) => match Authenticator::load(path) {
+                    Ok(auth) => Some(auth),
+                    Err(e) => {
+                        eprintln!("Failed to load auth keys: {}", e);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            // sherpa-onnx 的客户端发送识别器采样率下的 f32 单声道采样
+            let websocket_config = SessionConfig {
+                audio_config: AudioConfig {
+                    sample_rate: recognizer.sample_rate(),
+                    sample_format: SampleFormat::F32Le,
+                    ..AudioConfig::default()
+                },
+                result_format: ResultFormat::Json,
+            };
+            if let Ok(listener) = TcpListener::bind(addr).await {
+                let executor = TcpListenerExecutor::build_from(listener,
+                    websocket_listener,
+                    http_listener,
+                    grpc_listener,
+                    unix_listener,
+                    tls_acceptor,
+                    websocket_config,
+                    config.channel_num,
+                    config.channel_capacity,
+                    config.max_wait,
+                    config.http_max_body,
+                    config.read_timeout,
+                    recognizer.clone(),
+                    scheduler,
+                    config.protocol,
+                    SessionDefaults {
+                        audio_config: config.audio_config,
+                        result_format: config.result_format,
+                        model: config.model_config.name.clone(),
+                        language: config.model_config.language.clone(),
+                    },
+                    auth).await;
+                if executor.channels.channels.is_empty() {
+                    eprintln!("No channel opened, server init failed");
+                    return None;
+                }
+                Some(Self {
+                    recognizer,
+                    executor,
+                })
+            } else {
+                None
             }
         }
 
-        fn run(&self) -> impl std::future::Future<Output = ()> {
-            async move {
-                self.executor.execute().await;
-            }
+        async fn run(&self) {
+            self.executor.execute().await;
         }
     }
 }
\ No newline at end of file
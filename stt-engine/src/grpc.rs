@@ -0,0 +1,100 @@
+use tokio::sync::mpsc;
+use tonic::{Code, Status};
+
+use crate::{protocol::{ErrorCode, ServerError, StartConfig}, transcript::TranscriptEvent};
+
+use self::proto::{RecognitionConfig, StreamingRecognizeResponse, TranscriptResult};
+
+/// 由 `proto/stt.proto` 生成的消息和服务
+pub mod proto {
+    tonic::include_proto!("stt.v1");
+}
+
+/// 把 gRPC 的识别配置转换成 Start 帧的参数，空值表示使用服务端默认配置
+pub fn start_config(config: RecognitionConfig) -> Result<StartConfig, Status> {
+    let mut fields = Vec::new();
+    let mut push = |key: &str, value: String| {
+        if !value.is_empty() {
+            fields.push(format!("{}={}", key, value));
+        }
+    };
+    // 数值字段为 0 表示没有设置
+    let number = |value: u32| if value > 0 { value.to_string() } else { String::new() };
+    push("container", config.container);
+    push("sample_rate", number(config.sample_rate));
+    push("sample_format", config.sample_format);
+    push("channels", number(config.channels));
+    push("channel_mode", config.channel_mode);
+    push("channel_labels", config.channel_labels.join(","));
+    push("model", config.model);
+    push("language", config.language);
+    let invalid = |message: String| status(&ServerError::new(ErrorCode::InvalidRequest, message));
+    if let Some(field) = fields.iter().find(|field| field.contains('\n')) {
+        return Err(invalid(format!("Invalid config field: {:?}", field)));
+    }
+    StartConfig::decode(&fields.join("\n")).map_err(invalid)
+}
+
+/// 错误类型对应的 gRPC 状态码，消息是 `<code> <message>`，客户端可以从中取出本服务的错误类型
+pub fn status(error: &ServerError) -> Status {
+    let code = match error.code {
+        ErrorCode::Busy => Code::ResourceExhausted,
+        ErrorCode::InvalidAudio | ErrorCode::InvalidRequest => Code::InvalidArgument,
+        ErrorCode::RecognizerError => Code::Internal,
+        ErrorCode::Timeout => Code::DeadlineExceeded,
+        ErrorCode::UnsupportedVersion => Code::FailedPrecondition,
+        ErrorCode::UnsupportedModel => Code::NotFound,
+        ErrorCode::Unauthorized => Code::Unauthenticated,
+        ErrorCode::QuotaExceeded => Code::ResourceExhausted,
+    };
+    Status::new(code, error.to_payload())
+}
+
+impl From<&TranscriptEvent> for TranscriptResult {
+    fn from(event: &TranscriptEvent) -> Self {
+        Self {
+            is_final: event.is_final(),
+            channel: event.channel.clone(),
+            segment: event.segment as u32,
+            text: event.text.clone(),
+            start: event.start,
+            end: event.end,
+            tokens: event.tokens.clone(),
+            timestamps: event.timestamps.clone(),
+        }
+    }
+}
+
+/// 把识别结果发送给 gRPC 响应流，出错后不再发送后面的结果
+pub struct GrpcSink {
+    sender: mpsc::Sender<Result<StreamingRecognizeResponse, Status>>,
+    session_id: String,
+    failed: bool,
+}
+
+impl GrpcSink {
+    pub fn new(sender: mpsc::Sender<Result<StreamingRecognizeResponse, Status>>, session_id: String) -> Self {
+        Self { sender, session_id, failed: false }
+    }
+
+    // 客户端取消请求后接收端被丢弃，和 TCP 连接一样报告 BrokenPipe
+    async fn send(&mut self, item: Result<StreamingRecognizeResponse, Status>) -> std::io::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        self.failed = item.is_err();
+        self.sender.send(item).await.map_err(|_| std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    pub async fn send_event(&mut self, event: &TranscriptEvent) -> std::io::Result<()> {
+        let response = StreamingRecognizeResponse {
+            session_id: self.session_id.clone(),
+            result: Some(event.into()),
+        };
+        self.send(Ok(response)).await
+    }
+
+    pub async fn send_error(&mut self, error: &ServerError) -> std::io::Result<()> {
+        self.send(Err(status(error))).await
+    }
+}
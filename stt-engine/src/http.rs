@@ -0,0 +1,525 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, time::timeout};
+
+use crate::{codec, auth, openai::{self, TRANSCRIPTIONS_PATH}, protocol::{ErrorCode, ServerError, SessionConfig, SessionDefaults, StartConfig}, transcript::TranscriptEvent, wav::AudioContainer};
+
+pub const TRANSCRIBE_PATH: &str = "/v1/transcribe";
+
+/// 请求体大小的默认上限，请求体在分配通道前要完整缓存在内存中
+pub const DEFAULT_MAX_BODY: usize = 64 << 20;
+
+// 请求头的上限，超过后不再等待剩余的头部
+const MAX_HEAD_SIZE: usize = 16 << 10;
+const MAX_HEADERS: usize = 64;
+
+/// 一个完整的 HTTP 请求，服务端每个连接只处理一个请求
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub content_type: Option<String>,
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// 请求处理失败时返回的状态码和错误
+#[derive(Debug, Clone)]
+pub struct HttpError {
+    pub status: u16,
+    pub error: ServerError,
+}
+
+impl HttpError {
+    pub fn new(status: u16, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { status, error: ServerError::new(code, message) }
+    }
+}
+
+impl From<ServerError> for HttpError {
+    fn from(error: ServerError) -> Self {
+        Self { status: status_of(error.code), error }
+    }
+}
+
+/// 错误类型对应的 HTTP 状态码
+pub fn status_of(code: ErrorCode) -> u16 {
+    match code {
+        ErrorCode::Busy => 503,
+        ErrorCode::InvalidAudio => 415,
+        ErrorCode::RecognizerError => 500,
+        ErrorCode::Timeout => 408,
+        ErrorCode::UnsupportedVersion | ErrorCode::UnsupportedModel | ErrorCode::InvalidRequest => 400,
+        ErrorCode::Unauthorized => 401,
+        ErrorCode::QuotaExceeded => 429,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        401 => "Unauthorized",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+async fn read_some<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>, read_timeout: Duration) -> Result<(), HttpError> {
+    let mut chunk = [0; 4096];
+    let n = timeout(read_timeout, reader.read(&mut chunk)).await
+        .map_err(|_| HttpError::new(408, ErrorCode::Timeout, format!("No data received from client in {}s", read_timeout.as_secs())))?
+        .map_err(|e| HttpError::new(400, ErrorCode::InvalidRequest, e.to_string()))?;
+    if n == 0 {
+        return Err(HttpError::new(400, ErrorCode::InvalidRequest, "Connection closed before the request was complete"));
+    }
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(())
+}
+
+/// 已经读完的请求头，服务端先按请求头认证，再读取请求体
+pub struct RequestHead {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub content_type: Option<String>,
+    pub authorization: Option<String>,
+    content_length: usize,
+    expect_continue: bool,
+    // 和请求头一起读到的请求体开头
+    body: Vec<u8>,
+}
+
+/// 读取请求头，请求体必须带 `Content-Length`。
+pub async fn read_head<S: AsyncRead + Unpin>(stream: &mut S, read_timeout: Duration) -> Result<RequestHead, HttpError> {
+    let mut buf = Vec::new();
+    loop {
+        read_some(stream, &mut buf, read_timeout).await?;
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buf) {
+            Ok(httparse::Status::Complete(head_len)) => {
+                let header = |name: &str| request.headers.iter()
+                    .find(|header| header.name.eq_ignore_ascii_case(name))
+                    .map(|header| String::from_utf8_lossy(header.value).trim().to_string());
+                if header("transfer-encoding").is_some() {
+                    return Err(HttpError::new(411, ErrorCode::InvalidRequest, "Chunked requests are not supported, Content-Length is required"));
+                }
+                let content_length = match header("content-length") {
+                    Some(value) => value.parse::<usize>()
+                        .map_err(|_| HttpError::new(400, ErrorCode::InvalidRequest, format!("Invalid Content-Length: {}", value)))?,
+                    None => 0,
+                };
+                let target = request.path.unwrap_or("");
+                let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                return Ok(RequestHead {
+                    method: request.method.unwrap_or("").to_string(),
+                    path: path.to_string(),
+                    query: query.to_string(),
+                    content_type: header("content-type"),
+                    authorization: header("authorization"),
+                    content_length,
+                    expect_continue: header("expect").is_some_and(|value| value.eq_ignore_ascii_case("100-continue")),
+                    body: buf.split_off(head_len),
+                });
+            },
+            Ok(httparse::Status::Partial) if buf.len() < MAX_HEAD_SIZE => continue,
+            Ok(httparse::Status::Partial) => return Err(HttpError::new(431, ErrorCode::InvalidRequest, "Request header is too large")),
+            Err(e) => return Err(HttpError::new(400, ErrorCode::InvalidRequest, format!("Invalid request: {}", e))),
+        }
+    }
+}
+
+impl RequestHead {
+    /// 请求的接口，请求体还没读取时用来决定错误消息的格式
+    pub fn api(&self) -> Api {
+        match self.path.as_str() {
+            TRANSCRIPTIONS_PATH => Api::OpenAi(openai::Options::default()),
+            _ => Api::Transcribe,
+        }
+    }
+
+    /// `Authorization: Bearer` 中的凭证
+    pub fn credential(&self) -> Option<&str> {
+        self.authorization.as_deref().and_then(auth::bearer)
+    }
+
+    /// 读取请求体，大小不超过 `max_body`。
+    ///
+    /// 客户端发送了 `Expect: 100-continue` 时先回复 100，curl 上传大文件时会这样做。
+    pub async fn read_body<S: AsyncRead + AsyncWrite + Unpin>(self, stream: &mut S, max_body: usize, read_timeout: Duration) -> Result<Request, HttpError> {
+        let RequestHead { method, path, query, content_type, authorization, content_length, expect_continue, mut body } = self;
+        if content_length > max_body {
+            return Err(HttpError::new(413, ErrorCode::InvalidRequest, format!("Request body exceeds {} bytes", max_body)));
+        }
+        if expect_continue && body.len() < content_length {
+            let head = format!("HTTP/1.1 100 {}\r\n\r\n", reason(100));
+            stream.write_all(head.as_bytes()).await
+                .map_err(|e| HttpError::new(400, ErrorCode::InvalidRequest, e.to_string()))?;
+        }
+        while body.len() < content_length {
+            read_some(stream, &mut body, read_timeout).await?;
+        }
+        // 一个连接只处理一个请求，多出来的数据直接丢弃
+        body.truncate(content_length);
+        Ok(Request { method, path, query, content_type, authorization, body })
+    }
+}
+
+/// 请求的接口，决定识别结果和错误消息的格式
+#[derive(Debug, Clone)]
+pub enum Api {
+    /// `POST /v1/transcribe`
+    Transcribe,
+    /// OpenAI 兼容的 `POST /v1/audio/transcriptions`
+    OpenAi(openai::Options),
+}
+
+/// 一个要识别的请求
+pub struct Job {
+    pub api: Api,
+    pub audio: Vec<u8>,
+    pub config: SessionConfig,
+}
+
+/// 按路径分发请求，解析出音频和会话配置；出错时同时返回错误应该使用的接口格式
+pub fn route(request: Request, defaults: &SessionDefaults) -> Result<Job, (Api, HttpError)> {
+    let api = match request.path.as_str() {
+        TRANSCRIBE_PATH => Api::Transcribe,
+        TRANSCRIPTIONS_PATH => Api::OpenAi(openai::Options::default()),
+        path => return Err((Api::Transcribe, HttpError::new(404, ErrorCode::InvalidRequest, format!("Unknown path: {}", path)))),
+    };
+    if request.method != "POST" {
+        return Err((api, HttpError::new(405, ErrorCode::InvalidRequest, format!("Method {} is not allowed", request.method))));
+    }
+    let (api, audio, mut start) = match api {
+        Api::Transcribe => {
+            let start = parse_query(&request.query)
+                .map_err(|e| (Api::Transcribe, HttpError::new(400, ErrorCode::InvalidRequest, e)))?;
+            // 凭证放在 URL 中会出现在代理和访问日志里，只接受请求头
+            if start.auth.is_some() {
+                return Err((Api::Transcribe, HttpError::new(400, ErrorCode::InvalidRequest,
+                    "Send the credential in the Authorization header, not in the URL")));
+            }
+            (Api::Transcribe, request.body, start)
+        },
+        Api::OpenAi(options) => {
+            let parts = match request.content_type.as_deref() {
+                Some(content_type) => parse_multipart(content_type, &request.body),
+                None => Err("Content-Type must be multipart/form-data".to_string()),
+            };
+            let (audio, start, options) = parts
+                .map_err(|e| HttpError::new(400, ErrorCode::InvalidRequest, e))
+                .and_then(|parts| openai::parse_form(parts, &defaults.language))
+                .map_err(|e| (Api::OpenAi(options), e))?;
+            (Api::OpenAi(options), audio, start)
+        },
+    };
+    // 没有指定容器时按文件头判断，都不是则按裸 PCM 处理
+    if start.container.is_none() {
+        start.container = detect_container(&audio);
+    }
+    if start.container == Some(AudioContainer::Compressed) && codec::is_opus(&audio) {
+        return Err((api, HttpError::new(400, ErrorCode::InvalidRequest, codec::OPUS_UNSUPPORTED)));
+    }
+    match start.session_config(defaults) {
+        Ok(config) => Ok(Job { api, audio, config }),
+        Err(e) => Err((api, e.into())),
+    }
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, status: u16, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    let head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, reason(status), content_type, body.len());
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await
+}
+
+/// `/v1/transcribe` 的错误与 JSON 结果格式的错误消息一致，OpenAI 接口使用 OpenAI 的错误格式
+pub async fn write_error<W: AsyncWrite + Unpin>(writer: &mut W, api: &Api, error: &HttpError, session_id: &str) -> std::io::Result<()> {
+    let body = match api {
+        Api::Transcribe => error.error.to_json(session_id).trim_end().to_string(),
+        Api::OpenAi(_) => openai::error_body(error),
+    };
+    write_response(writer, error.status, "application/json", body.as_bytes()).await
+}
+
+/// 解析查询参数，参数名与 Start 帧的字段相同，例如 `?sample_rate=8000&sample_format=s16le`
+pub fn parse_query(query: &str) -> Result<StartConfig, String> {
+    let mut lines = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let (key, value) = (percent_decode(key)?, percent_decode(value)?);
+        if key.contains('\n') || value.contains('\n') {
+            return Err(format!("Invalid query parameter: {}", pair));
+        }
+        lines.push(format!("{}={}", key, value));
+    }
+    StartConfig::decode(&lines.join("\n"))
+}
+
+fn percent_decode(value: &str) -> Result<String, String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let byte = value.get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(format!("Invalid percent encoding: {}", value))?;
+                decoded.push(byte);
+                i += 2;
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| format!("Invalid percent encoding: {}", value))
+}
+
+/// multipart/form-data 中的一个字段
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn text(&self) -> Result<String, String> {
+        String::from_utf8(self.data.clone()).map(|text| text.trim().to_string())
+            .map_err(|_| format!("Field {} is not valid UTF-8", self.name))
+    }
+}
+
+/// 解析 multipart/form-data 请求体，`content_type` 中必须带 boundary
+pub fn parse_multipart(content_type: &str, body: &[u8]) -> Result<Vec<Part>, String> {
+    let mut params = content_type.split(';').map(str::trim);
+    if !params.next().is_some_and(|mime| mime.eq_ignore_ascii_case("multipart/form-data")) {
+        return Err(format!("Content-Type must be multipart/form-data, got {}", content_type));
+    }
+    let boundary = params.find_map(|param| param.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty())
+        .ok_or("Missing multipart boundary".to_string())?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let truncated = || "Truncated multipart body".to_string();
+
+    let mut parts = Vec::new();
+    let mut rest = &body[find(body, &delimiter).ok_or(truncated())? + delimiter.len()..];
+    loop {
+        // 最后一个分隔符后面跟着 `--`
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest.strip_prefix(b"\r\n").ok_or(truncated())?;
+        let mut headers = [httparse::EMPTY_HEADER; 16];
+        let (head_len, disposition) = match httparse::parse_headers(rest, &mut headers) {
+            Ok(httparse::Status::Complete((head_len, headers))) => (head_len, headers.iter()
+                .find(|header| header.name.eq_ignore_ascii_case("content-disposition"))
+                .map(|header| String::from_utf8_lossy(header.value).to_string())),
+            Ok(httparse::Status::Partial) => return Err(truncated()),
+            Err(e) => return Err(format!("Invalid multipart header: {}", e)),
+        };
+        rest = &rest[head_len..];
+        let mut end_delimiter = b"\r\n".to_vec();
+        end_delimiter.extend_from_slice(&delimiter);
+        let end = find(rest, &end_delimiter).ok_or(truncated())?;
+        let disposition = disposition.ok_or("Missing Content-Disposition in multipart field".to_string())?;
+        let param = |key: &str| disposition.split(';').map(str::trim)
+            .find_map(|param| param.strip_prefix(key).and_then(|value| value.strip_prefix('=')))
+            .map(|value| value.trim_matches('"').to_string());
+        parts.push(Part {
+            name: param("name").ok_or("Missing field name in multipart field".to_string())?,
+            filename: param("filename"),
+            data: rest[..end].to_vec(),
+        });
+        rest = &rest[end + end_delimiter.len()..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// 根据文件头判断请求体的容器格式，裸 PCM 没有文件头，返回 `None`
+pub fn detect_container(body: &[u8]) -> Option<AudioContainer> {
+    if body.starts_with(b"RIFF") {
+        Some(AudioContainer::Wav)
+    } else if body.starts_with(b"fLaC") || body.starts_with(b"OggS") || body.starts_with(b"ID3") || is_mp3_frame(body) {
+        Some(AudioContainer::Compressed)
+    } else {
+        None
+    }
+}
+
+// 没有 ID3 标签的 MP3 直接以帧头开始：11 位同步字，之后的版本、层、码率和采样率都要是有效值，
+// 只认 Layer III，避免把 0xFFFF 这样的 PCM 采样误认为帧头
+fn is_mp3_frame(body: &[u8]) -> bool {
+    let [0xFF, b1, b2, ..] = body else {
+        return false;
+    };
+    b1 & 0xE0 == 0xE0
+        && (b1 >> 3) & 0x03 != 0x01
+        && (b1 >> 1) & 0x03 == 0x01
+        && b2 >> 4 != 0x0F
+        && (b2 >> 2) & 0x03 != 0x03
+}
+
+// POST /v1/transcribe 的响应
+#[derive(Serialize)]
+struct TranscribeResponse<'a> {
+    session_id: &'a str,
+    text: String,
+    segments: Vec<Segment<'a>>,
+}
+
+#[derive(Serialize)]
+struct Segment<'a> {
+    channel: Option<&'a str>,
+    segment: usize,
+    text: &'a str,
+    start: f32,
+    end: f32,
+    tokens: &'a [String],
+    timestamps: &'a [f32],
+}
+
+/// 收集一次请求的识别结果，识别完成后按请求的接口一次性写出响应。
+///
+/// 只保留 final 结果；一个请求只写一次响应，出错后不会再写识别结果。
+pub struct Responder<W> {
+    writer: W,
+    api: Api,
+    session_id: String,
+    segments: Vec<TranscriptEvent>,
+    responded: bool,
+}
+
+impl<W: AsyncWrite + Unpin> Responder<W> {
+    pub fn new(writer: W, api: Api, session_id: String) -> Self {
+        Self { writer, api, session_id, segments: Vec::new(), responded: false }
+    }
+
+    pub fn push(&mut self, event: &TranscriptEvent) {
+        if event.is_final() {
+            self.segments.push(event.clone());
+        }
+    }
+
+    pub async fn respond(&mut self) -> std::io::Result<()> {
+        if std::mem::replace(&mut self.responded, true) {
+            return Ok(());
+        }
+        if let Api::OpenAi(options) = &self.api {
+            let (content_type, body) = openai::render(options, &self.segments);
+            return write_response(&mut self.writer, 200, content_type, body.as_bytes()).await;
+        }
+        let response = TranscribeResponse {
+            session_id: &self.session_id,
+            text: self.segments.iter().map(|event| event.text.as_str()).collect::<Vec<_>>().join(" "),
+            segments: self.segments.iter().map(|event| Segment {
+                channel: event.channel.as_deref(),
+                segment: event.segment,
+                text: &event.text,
+                start: event.start,
+                end: event.end,
+                tokens: &event.tokens,
+                timestamps: &event.timestamps,
+            }).collect(),
+        };
+        let body = serde_json::to_string(&response).unwrap_or_default();
+        write_response(&mut self.writer, 200, "application/json", body.as_bytes()).await
+    }
+
+    pub async fn respond_error(&mut self, error: &ServerError) -> std::io::Result<()> {
+        if std::mem::replace(&mut self.responded, true) {
+            return Ok(());
+        }
+        write_error(&mut self.writer, &self.api, &HttpError::from(error.clone()), &self.session_id).await
+    }
+
+    pub async fn shutdown(&mut self) -> std::io::Result<()> {
+        self.writer.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{audio::AudioConfig, transcript::ResultFormat};
+
+    use super::*;
+
+    #[test]
+    fn detects_container_from_header() {
+        assert_eq!(detect_container(b"RIFF\0\0\0\0WAVE"), Some(AudioContainer::Wav));
+        for header in [&b"fLaC"[..], b"OggS", b"ID3\x04", &[0xFF, 0xFB, 0x90, 0x64], &[0xFF, 0xF3, 0x48, 0xC4], &[0xFF, 0xE3, 0x18, 0x00]] {
+            assert_eq!(detect_container(header), Some(AudioContainer::Compressed), "{:02x?}", header);
+        }
+        // 静音的 PCM 和不合法的帧头都按裸 PCM 处理
+        for header in [&[0xFF, 0xFF, 0xFF, 0xFF][..], &[0x00, 0x00], &[0xFF, 0xFD, 0x90], &[0xFF, 0xFB, 0xF0], &[0xFF, 0xFB, 0x9C], &[0xFF, 0xEB, 0x90], &[0xFF]] {
+            assert_eq!(detect_container(header), None, "{:02x?}", header);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_head_before_body() {
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+        client.write_all(b"POST /v1/transcribe?sample_rate=8000 HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 8\r\n\r\nabcd").await.unwrap();
+        let head = read_head(&mut server, Duration::from_secs(1)).await.unwrap();
+        assert_eq!((head.method.as_str(), head.path.as_str(), head.query.as_str()), ("POST", TRANSCRIBE_PATH, "sample_rate=8000"));
+        assert_eq!(head.credential(), Some("secret"));
+        // 请求体还没有读完
+        client.write_all(b"efghij").await.unwrap();
+        let request = head.read_body(&mut server, 8, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(request.body, b"abcdefgh");
+
+        // 超过上限的请求体不读取
+        client.write_all(b"POST /v1/audio/transcriptions HTTP/1.1\r\nContent-Length: 9\r\n\r\n").await.unwrap();
+        let head = read_head(&mut server, Duration::from_secs(1)).await.unwrap();
+        assert!(matches!(head.api(), Api::OpenAi(_)));
+        assert_eq!(head.credential(), None);
+        let error = head.read_body(&mut server, 8, Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!((error.status, error.error.code), (413, ErrorCode::InvalidRequest));
+    }
+
+    #[test]
+    fn rejects_credential_in_query() {
+        let request = |query: &str, authorization: Option<&str>| Request {
+            method: "POST".to_string(),
+            path: TRANSCRIBE_PATH.to_string(),
+            query: query.to_string(),
+            content_type: None,
+            authorization: authorization.map(str::to_string),
+            body: vec![0; 320],
+        };
+        let defaults = SessionDefaults {
+            audio_config: AudioConfig::default(),
+            result_format: ResultFormat::default(),
+            model: String::new(),
+            language: String::new(),
+        };
+        let job = route(request("sample_rate=8000", Some("Bearer secret")), &defaults).unwrap();
+        assert_eq!(job.config.audio_config.sample_rate, 8000);
+
+        let Err((_, error)) = route(request("auth=secret", None), &defaults) else {
+            panic!("credential in the URL should be rejected");
+        };
+        assert_eq!((error.status, error.error.code), (400, ErrorCode::InvalidRequest));
+    }
+}
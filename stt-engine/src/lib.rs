@@ -1,4 +1,16 @@
 pub mod endpoint;
 pub mod client;
 pub mod sherpa;
+pub mod scheduler;
+pub mod transcript;
+pub mod wav;
+pub mod audio;
+pub mod codec;
+pub mod protocol;
+pub mod http;
+pub mod openai;
+pub mod grpc;
+pub mod tls;
+pub mod auth;
+pub mod pool;
 pub mod benchmark;
\ No newline at end of file
@@ -1,36 +1,178 @@
-use stt_engine::{benchmark, client, endpoint::{server::{Server, ServerConfig}, Endpoint}};
+use std::path::PathBuf;
+
+use stt_engine::{audio::AudioConfig, auth, benchmark, client::{self, Transport}, endpoint::{server::{Server, ServerConfig, UnixSocketConfig}, Endpoint}, http, protocol::ProtocolMode, scheduler::SchedulerConfig, sherpa::{ModelConfig, Sherpa}, tls::{ClientTlsConfig, ServerTlsConfig}, transcript::ResultFormat};
 
 #[tokio::main]
 // 主函数，解析参数如果是server则启动服务端，如果是client则启动客户端
 async fn main() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() < 2 {
-        println!("Usage: {} [server|client] [options]", &args[0]);
+        println!("Usage: {} [server|client|benchmark|token] [options]", &args[0]);
         return;
     }
+    // 服务端和客户端都通过 STT_PROTOCOL 选择协议，默认使用分帧协议
+    let protocol = match ProtocolMode::from_env() {
+        Ok(protocol) => protocol,
+        Err(e) => {
+            eprintln!("Failed to load protocol: {}", e);
+            return;
+        }
+    };
     match &*args[1] {
         "server" => {
-            let config = ServerConfig::new("0.0.0.0", 8888, 20, 20, false, 2, 2, 2);
-            if let Some(server) = Server::init(config).await {
-                println!("Server started on 0.0.0.0:8888");
+            let model_config = match ModelConfig::from_env() {
+                Ok(model_config) => model_config,
+                Err(e) => {
+                    eprintln!("Failed to load model config: {}", e);
+                    return;
+                }
+            };
+            let scheduler_config = match SchedulerConfig::from_env() {
+                Ok(scheduler_config) => scheduler_config,
+                Err(e) => {
+                    eprintln!("Failed to load scheduler config: {}", e);
+                    return;
+                }
+            };
+            let audio_config = match AudioConfig::from_env() {
+                Ok(audio_config) => audio_config,
+                Err(e) => {
+                    eprintln!("Failed to load audio config: {}", e);
+                    return;
+                }
+            };
+            let result_format = match ResultFormat::from_env() {
+                Ok(result_format) => result_format,
+                Err(e) => {
+                    eprintln!("Failed to load result format: {}", e);
+                    return;
+                }
+            };
+            // 设置了 STT_WEBSOCKET_PORT 时同时接受 sherpa-onnx 兼容的 WebSocket 连接
+            let websocket_port = match port_from_env("STT_WEBSOCKET_PORT") {
+                Ok(port) => port,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            // 设置了 STT_HTTP_PORT 时同时提供 HTTP 接口
+            let http_port = match port_from_env("STT_HTTP_PORT") {
+                Ok(port) => port,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            // HTTP 请求体最多 STT_HTTP_MAX_BODY 字节
+            let http_max_body = match std::env::var("STT_HTTP_MAX_BODY") {
+                Ok(value) => match value.parse() {
+                    Ok(http_max_body) => http_max_body,
+                    Err(e) => {
+                        eprintln!("Invalid STT_HTTP_MAX_BODY {}: {}", value, e);
+                        return;
+                    }
+                },
+                Err(_) => http::DEFAULT_MAX_BODY,
+            };
+            // 设置了 STT_GRPC_PORT 时同时提供 gRPC 服务
+            let grpc_port = match port_from_env("STT_GRPC_PORT") {
+                Ok(port) => port,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            // 设置了 STT_UNIX_SOCKET 时同时在 Unix domain socket 上监听
+            let unix_socket = match UnixSocketConfig::from_env() {
+                Ok(unix_socket) => unix_socket,
+                Err(e) => {
+                    eprintln!("Failed to load unix socket config: {}", e);
+                    return;
+                }
+            };
+            // 设置了 STT_TLS_CERT 和 STT_TLS_KEY 时 TCP 监听只接受 TLS 连接
+            let tls = match ServerTlsConfig::from_env() {
+                Ok(tls) => tls,
+                Err(e) => {
+                    eprintln!("Failed to load TLS config: {}", e);
+                    return;
+                }
+            };
+            let tls_enabled = tls.is_some();
+            // 设置了 STT_AUTH_KEYS 时按密钥文件认证客户端
+            let auth_keys = std::env::var("STT_AUTH_KEYS").ok().map(PathBuf::from);
+            // 没有空闲通道时最多排队等待 STT_CHANNEL_MAX_WAIT 秒，默认直接返回繁忙
+            let max_wait = match std::env::var("STT_CHANNEL_MAX_WAIT") {
+                Ok(value) => match value.parse() {
+                    Ok(max_wait) => max_wait,
+                    Err(e) => {
+                        eprintln!("Invalid STT_CHANNEL_MAX_WAIT {}: {}", value, e);
+                        return;
+                    }
+                },
+                Err(_) => 0,
+            };
+            let config = ServerConfig {
+                websocket_port,
+                http_port,
+                http_max_body,
+                grpc_port,
+                unix_socket,
+                tls,
+                auth_keys,
+                max_wait,
+                protocol,
+                result_format,
+                audio_config,
+                scheduler_config,
+                model_config,
+                ..Default::default()
+            };
+            if let Some(server) = Server::<Sherpa>::init(config).await {
+                if let Some(addr) = server.local_addr() {
+                    println!("Server started on {}{}", addr, if tls_enabled { " (TLS)" } else { "" });
+                }
+                if let Some(addr) = server.websocket_addr() {
+                    println!("WebSocket listening on {}", addr);
+                }
+                if let Some(addr) = server.http_addr() {
+                    println!("HTTP listening on {}", addr);
+                }
+                if let Some(addr) = server.grpc_addr() {
+                    println!("gRPC listening on {}", addr);
+                }
+                if let Some(path) = server.unix_socket_path() {
+                    println!("Unix socket listening on {}", path.display());
+                }
                 server.run().await;
             }
         }, 
         "client" => {
-            // 如果args长度为3，则第三个参数为服务器地址，否则默认为127.0.0.1
+            // 如果args长度为3，则第三个参数为服务器地址，否则默认为127.0.0.1；unix:/path 表示通过 Unix domain socket 连接
             let server_addr = if args.len() == 3 {
                 args[2].clone()
             } else {
                 "127.0.0.1".to_string()
             };
+            let transport = match transport_from(&server_addr) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    eprintln!("Failed to load TLS config: {}", e);
+                    return;
+                }
+            };
+            // 服务端开启认证时通过 STT_AUTH 提供密钥或令牌
+            let auth = std::env::var("STT_AUTH").ok();
             let start_time = std::time::Instant::now();
             let mut joints = Vec::new();
             for i in 0..20 {
-                let server_addr = server_addr.clone();
+                let transport = transport.clone();
+                let auth = auth.clone();
                 let joint = tokio::spawn(async move {
                     let wav_file = format!("./data/segment/split_part_{}.wav", i + 1);
                     println!("Sending file: {}", wav_file);
-                    match client::run_with(server_addr, 8888, wav_file, false).await {
+                    match client::run_on(transport, auth, wav_file, protocol, false).await {
                         Ok(res) => {
                             println!("Received response: {:?}", res);
                         },
@@ -49,16 +191,55 @@ async fn main() {
             println!("Total exectute time: {:?}", end_time - start_time);
         },
         "benchmark" => {
-            // 如果args长度为3，则第三个参数为服务器地址，否则默认为127.0.0.1
+            // 如果args长度为3，则第三个参数为服务器地址，否则默认为127.0.0.1；unix:/path 表示通过 Unix domain socket 连接
             let server_addr = if args.len() == 3 {
                 args[2].clone()
             } else {
                 "127.0.0.1".to_string()
             };
-            benchmark::run_benchmark(server_addr, 8888, 30).await;
+            let transport = match transport_from(&server_addr) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    eprintln!("Failed to load TLS config: {}", e);
+                    return;
+                }
+            };
+            benchmark::run_benchmark(transport, std::env::var("STT_AUTH").ok(), 30, protocol).await;
+        },
+        "token" => {
+            // 用密钥签发一个 ttl_seconds 秒后过期的令牌
+            let [_, _, id, secret, ttl] = &args[..] else {
+                println!("Usage: {} token <id> <secret> <ttl_seconds>", &args[0]);
+                return;
+            };
+            let ttl: u64 = match ttl.parse() {
+                Ok(ttl) => ttl,
+                Err(e) => {
+                    eprintln!("Invalid ttl_seconds {}: {}", ttl, e);
+                    return;
+                }
+            };
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+            println!("{}", auth::sign_token(id, secret, now + ttl));
         },
         _ => {
             println!("Unknown command: {}", &args[1]);
         }
     };
-}
\ No newline at end of file
+}
+
+// 可选的端口配置，没有设置时返回 None
+fn port_from_env(key: &str) -> Result<Option<u16>, String> {
+    match std::env::var(key) {
+        Ok(port) => port.parse().map(Some).map_err(|e| format!("Invalid {} {}: {}", key, port, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+// 设置了 STT_TLS_CA 时通过 TLS 连接 TCP 地址
+fn transport_from(addr: &str) -> Result<Transport, String> {
+    match (Transport::parse(addr, 8888), ClientTlsConfig::from_env()) {
+        (Transport::Tcp(ip, port), Some(tls)) => Ok(Transport::Tls(ip, port, tls.connector()?)),
+        (transport, _) => Ok(transport),
+    }
+}
@@ -0,0 +1,229 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::{http::{HttpError, Part}, protocol::{ErrorCode, StartConfig}, transcript::TranscriptEvent, wav::AudioContainer};
+
+pub const TRANSCRIPTIONS_PATH: &str = "/v1/audio/transcriptions";
+
+/// OpenAI 转写接口的 `response_format`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseFormat {
+    #[default]
+    Json,
+    Text,
+    Srt,
+    VerboseJson,
+    Vtt,
+}
+
+impl FromStr for ResponseFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ResponseFormat::Json),
+            "text" => Ok(ResponseFormat::Text),
+            "srt" => Ok(ResponseFormat::Srt),
+            "verbose_json" => Ok(ResponseFormat::VerboseJson),
+            "vtt" => Ok(ResponseFormat::Vtt),
+            _ => Err(format!("Unknown response format: {}", s)),
+        }
+    }
+}
+
+impl ResponseFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "json",
+            ResponseFormat::Text => "text",
+            ResponseFormat::Srt => "srt",
+            ResponseFormat::VerboseJson => "verbose_json",
+            ResponseFormat::Vtt => "vtt",
+        }
+    }
+}
+
+/// 一次转写请求中影响响应内容的参数
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub response_format: ResponseFormat,
+    pub language: String,
+    /// `timestamp_granularities[]` 包含 `word` 时 verbose_json 带上逐词时间戳
+    pub word_timestamps: bool,
+}
+
+/// 解析表单，返回音频、会话参数和响应参数。
+///
+/// `model` 只为兼容保留，任何值都使用本地加载的模型；`language` 只是提示，与本地模型不一致时不报错，
+/// 只在 verbose_json 中原样返回；`prompt`、`temperature` 等参数忽略。
+pub fn parse_form(parts: Vec<Part>, default_language: &str) -> Result<(Vec<u8>, StartConfig, Options), HttpError> {
+    let invalid = |message: String| HttpError::new(400, ErrorCode::InvalidRequest, message);
+    let mut file = None;
+    let mut start = StartConfig::default();
+    let mut options = Options { language: default_language.to_string(), ..Options::default() };
+    for part in parts {
+        match part.name.as_str() {
+            "file" => file = Some(part.data),
+            "language" => options.language = part.text().map_err(invalid)?,
+            "response_format" => options.response_format = part.text().map_err(invalid)?.parse().map_err(invalid)?,
+            "timestamp_granularities[]" | "timestamp_granularities" => options.word_timestamps |= part.text().map_err(invalid)? == "word",
+            _ => {},
+        }
+    }
+    let file = file.ok_or(invalid("Missing required field: file".to_string()))?;
+    // 上传的总是完整的文件，认不出的格式交给 symphonia 探测
+    start.container = Some(crate::http::detect_container(&file).unwrap_or(AudioContainer::Compressed));
+    Ok((file, start, options))
+}
+
+#[derive(Serialize)]
+struct Transcription<'a> {
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct VerboseTranscription<'a> {
+    task: &'static str,
+    language: &'a str,
+    duration: f32,
+    text: &'a str,
+    segments: Vec<VerboseSegment<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words: Option<Vec<Word<'a>>>,
+}
+
+#[derive(Serialize)]
+struct VerboseSegment<'a> {
+    id: usize,
+    seek: usize,
+    start: f32,
+    end: f32,
+    text: &'a str,
+    tokens: [u32; 0],
+    temperature: f32,
+    avg_logprob: f32,
+    compression_ratio: f32,
+    no_speech_prob: f32,
+}
+
+#[derive(Serialize)]
+struct Word<'a> {
+    word: &'a str,
+    start: f32,
+    end: f32,
+}
+
+/// 按 `response_format` 生成响应，返回 Content-Type 和内容
+pub fn render(options: &Options, segments: &[TranscriptEvent]) -> (&'static str, String) {
+    let text = segments.iter().map(|event| event.text.as_str()).collect::<Vec<_>>().join(" ");
+    match options.response_format {
+        ResponseFormat::Json => ("application/json", serde_json::to_string(&Transcription { text: &text }).unwrap_or_default()),
+        ResponseFormat::Text => ("text/plain; charset=utf-8", format!("{}\n", text)),
+        ResponseFormat::Srt => {
+            let body = segments.iter().enumerate()
+                .map(|(i, event)| format!("{}\n{} --> {}\n{}\n\n", i + 1, timestamp(event.start, ','), timestamp(event.end, ','), event.text))
+                .collect();
+            ("application/x-subrip", body)
+        },
+        ResponseFormat::Vtt => {
+            let cues = segments.iter()
+                .map(|event| format!("{} --> {}\n{}\n\n", timestamp(event.start, '.'), timestamp(event.end, '.'), event.text))
+                .collect::<String>();
+            ("text/vtt", format!("WEBVTT\n\n{}", cues))
+        },
+        ResponseFormat::VerboseJson => {
+            let words = options.word_timestamps.then(|| segments.iter().flat_map(|event| {
+                event.tokens.iter().zip(&event.timestamps).enumerate().map(|(i, (token, start))| Word {
+                    word: token,
+                    start: *start,
+                    // 每个词持续到下一个词开始，最后一个词到句子结束
+                    end: event.timestamps.get(i + 1).copied().unwrap_or(event.end),
+                })
+            }).collect());
+            let transcription = VerboseTranscription {
+                task: "transcribe",
+                language: &options.language,
+                // 只统计到最后一句结束
+                duration: segments.iter().map(|event| event.end).fold(0.0, f32::max),
+                text: &text,
+                segments: segments.iter().enumerate().map(|(id, event)| VerboseSegment {
+                    id,
+                    seek: 0,
+                    start: event.start,
+                    end: event.end,
+                    text: &event.text,
+                    tokens: [],
+                    temperature: 0.0,
+                    avg_logprob: 0.0,
+                    compression_ratio: 0.0,
+                    no_speech_prob: 0.0,
+                }).collect(),
+                words,
+            };
+            ("application/json", serde_json::to_string(&transcription).unwrap_or_default())
+        },
+    }
+}
+
+// `HH:MM:SS,mmm`，vtt 的毫秒分隔符是 `.`
+fn timestamp(seconds: f32, separator: char) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!("{:02}:{:02}:{:02}{}{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, separator, millis % 1000)
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    message: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    param: Option<&'a str>,
+    code: &'static str,
+}
+
+/// OpenAI 格式的错误，`code` 是本服务的错误类型
+pub fn error_body(error: &HttpError) -> String {
+    let body = ErrorBody {
+        error: ErrorDetail {
+            message: &error.error.message,
+            kind: if error.status >= 500 { "server_error" } else { "invalid_request_error" },
+            param: None,
+            code: error.error.code.as_str(),
+        },
+    };
+    serde_json::to_string(&body).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(name: &str, data: &[u8]) -> Part {
+        Part { name: name.to_string(), filename: None, data: data.to_vec() }
+    }
+
+    #[test]
+    fn language_is_a_hint() {
+        let (_, _, options) = parse_form(vec![part("file", b"RIFF"), part("language", b"en")], "zh").unwrap();
+        assert_eq!(options.language, "en");
+        let (_, _, options) = parse_form(vec![part("file", b"RIFF"), part("language", b"ZH")], "zh").unwrap();
+        assert_eq!(options.language, "ZH");
+        let (_, _, options) = parse_form(vec![part("file", b"RIFF")], "zh").unwrap();
+        assert_eq!(options.language, "zh");
+    }
+
+    #[test]
+    fn language_is_only_echoed_in_verbose_json() {
+        let options = |response_format| Options { response_format, language: "en".to_string(), ..Options::default() };
+        let segments = [TranscriptEvent { text: "hello".to_string(), end: 1.0, ..TranscriptEvent::default() }];
+        let (_, body) = render(&options(ResponseFormat::VerboseJson), &segments);
+        assert!(body.contains(r#""language":"en""#), "{}", body);
+        let (_, body) = render(&options(ResponseFormat::Json), &segments);
+        assert_eq!(body, r#"{"text":"hello"}"#);
+    }
+}
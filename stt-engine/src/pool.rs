@@ -0,0 +1,147 @@
+use std::{collections::VecDeque, sync::{Mutex, MutexGuard}, time::Duration};
+
+use tokio::{sync::oneshot, time::timeout};
+
+/// 一组编号的槽位，取出和归还都在锁内完成，同一个槽位不会同时交给两个使用者。
+///
+/// 没有空闲槽位时按先来先到排队，归还的槽位直接交给队首的等待者。
+#[derive(Default)]
+pub struct Pool {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    idle: VecDeque<usize>,
+    waiters: VecDeque<oneshot::Sender<usize>>,
+}
+
+impl Pool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 取出一个槽位，没有空闲时最多等待 `max_wait`，为 0 时不等待
+    pub async fn acquire(&self, max_wait: Duration) -> Option<usize> {
+        let receiver = {
+            let mut state = self.state();
+            if let Some(slot) = state.idle.pop_front() {
+                return Some(slot);
+            }
+            if max_wait.is_zero() {
+                return None;
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiters.push_back(sender);
+            receiver
+        };
+        let mut waiter = Waiter { pool: self, receiver };
+        timeout(max_wait, &mut waiter.receiver).await.ok().and_then(Result::ok)
+    }
+
+    /// 归还槽位，有等待者时交给最早开始等待的一个；新建的槽位也通过这里加入
+    pub fn release(&self, mut slot: usize) {
+        let mut state = self.state();
+        while let Some(waiter) = state.waiters.pop_front() {
+            // 等待者已经超时或取消时发送失败，换下一个
+            match waiter.send(slot) {
+                Ok(()) => return,
+                Err(returned) => slot = returned,
+            }
+        }
+        state.idle.push_back(slot);
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// 等待结束时关闭接收端，超时或取消的同时刚好收到的槽位放回池中，不会丢失
+struct Waiter<'a> {
+    pool: &'a Pool,
+    receiver: oneshot::Receiver<usize>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.receiver.close();
+        if let Ok(slot) = self.receiver.try_recv() {
+            self.pool.release(slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    const LONG_WAIT: Duration = Duration::from_secs(60);
+
+    // 在后台开始等待，直到它进入队列才返回
+    async fn wait(pool: &Arc<Pool>) -> tokio::task::JoinHandle<Option<usize>> {
+        let waiters = pool.state().waiters.len();
+        let handle = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire(LONG_WAIT).await }
+        });
+        while pool.state().waiters.len() == waiters {
+            tokio::task::yield_now().await;
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn hands_released_slots_to_waiters_in_order() {
+        let pool = Arc::new(Pool::new());
+        pool.release(0);
+        assert_eq!(pool.acquire(Duration::ZERO).await, Some(0));
+
+        let first = wait(&pool).await;
+        let second = wait(&pool).await;
+        let third = wait(&pool).await;
+        pool.release(7);
+        pool.release(8);
+        pool.release(9);
+        assert_eq!(first.await.unwrap(), Some(7));
+        assert_eq!(second.await.unwrap(), Some(8));
+        assert_eq!(third.await.unwrap(), Some(9));
+        assert!(pool.state().idle.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_wait() {
+        let pool = Pool::new();
+        // 不等待时直接返回，也不进入队列
+        assert_eq!(pool.acquire(Duration::ZERO).await, None);
+        assert!(pool.state().waiters.is_empty());
+
+        assert_eq!(pool.acquire(Duration::from_millis(20)).await, None);
+        // 超时的等待者被跳过，槽位回到空闲列表
+        pool.release(3);
+        assert_eq!(pool.state().idle, [3]);
+        assert_eq!(pool.acquire(Duration::ZERO).await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn keeps_slot_sent_to_cancelled_waiter() {
+        let pool = Arc::new(Pool::new());
+        let cancelled = wait(&pool).await;
+        let next = wait(&pool).await;
+        // 槽位已经发给第一个等待者，它还没来得及取走就被取消，槽位转给下一个等待者
+        pool.release(5);
+        cancelled.abort();
+        assert!(cancelled.await.unwrap_err().is_cancelled());
+        assert_eq!(next.await.unwrap(), Some(5));
+
+        // 没有其他等待者时回到空闲列表
+        let cancelled = wait(&pool).await;
+        pool.release(6);
+        cancelled.abort();
+        assert!(cancelled.await.unwrap_err().is_cancelled());
+        assert_eq!(pool.state().idle, [6]);
+        assert!(pool.state().waiters.is_empty());
+    }
+}
@@ -0,0 +1,606 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{audio::{check_channels, is_valid_label, AudioConfig, ChannelMode, SampleFormat}, transcript::ResultFormat, wav::AudioContainer};
+
+// 当前实现的协议版本，服务端按从高到低的顺序选择双方都支持的版本
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const SUPPORTED_VERSIONS: &[u8] = &[PROTOCOL_VERSION];
+
+// 单个帧的负载上限，音频需要拆成多个 Audio 帧发送
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+// 帧头：1 字节类型 + 4 字节大端负载长度
+const FRAME_HEADER_SIZE: usize = 5;
+
+/// 服务端和客户端之间使用的协议
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProtocolMode {
+    /// 带版本协商和会话握手的分帧协议
+    #[default]
+    Framed,
+    /// 旧的裸协议：客户端直接发送音频，关闭写端表示结束，服务端每行返回一条识别消息
+    Raw,
+}
+
+impl FromStr for ProtocolMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "framed" => Ok(ProtocolMode::Framed),
+            "raw" => Ok(ProtocolMode::Raw),
+            _ => Err(format!("Unknown protocol: {}", s)),
+        }
+    }
+}
+
+impl ProtocolMode {
+    /// 读取 `STT_PROTOCOL`，默认使用分帧协议
+    pub fn from_env() -> Result<Self, String> {
+        let mut mode = Self::default();
+        crate::sherpa::env_override("STT_PROTOCOL", &mut mode)?;
+        Ok(mode)
+    }
+}
+
+/// 分帧协议的帧，线上格式为 `<type: u8><length: u32 大端><payload>`。
+///
+/// 一次会话的顺序是：客户端发送 `Hello`（支持的版本列表），服务端回复 `Hello`（选定的版本）；
+/// 客户端发送 `Start` 声明音频参数，服务端分配到通道后回复 `Ready`（会话 ID）；之后客户端发送若干 `Audio`，
+/// 以 `End` 结束，服务端在识别过程中返回 `Result`，输出最后的结果后发送 `End` 并关闭连接。
+/// 任何一步出错时服务端发送带错误码的 `Error` 并关闭连接。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Hello(Vec<u8>),
+    Start(StartConfig),
+    Ready(String),
+    Audio(Vec<u8>),
+    End,
+    /// 一条识别消息，格式与裸协议的一行相同，不带换行
+    Result(String),
+    Error(ServerError),
+}
+
+const FRAME_HELLO: u8 = 0x01;
+const FRAME_START: u8 = 0x02;
+const FRAME_READY: u8 = 0x03;
+const FRAME_AUDIO: u8 = 0x04;
+const FRAME_END: u8 = 0x05;
+const FRAME_RESULT: u8 = 0x06;
+const FRAME_ERROR: u8 = 0x07;
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let (frame_type, payload) = match self {
+            Frame::Hello(versions) => (FRAME_HELLO, versions.clone()),
+            Frame::Start(config) => (FRAME_START, config.encode().into_bytes()),
+            Frame::Ready(session_id) => (FRAME_READY, session_id.as_bytes().to_vec()),
+            Frame::Audio(data) => (FRAME_AUDIO, data.clone()),
+            Frame::End => (FRAME_END, Vec::new()),
+            Frame::Result(line) => (FRAME_RESULT, line.as_bytes().to_vec()),
+            Frame::Error(error) => (FRAME_ERROR, error.to_payload().into_bytes()),
+        };
+        let mut bytes = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+        bytes.push(frame_type);
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    fn decode(frame_type: u8, payload: Vec<u8>) -> Result<Self, String> {
+        let text = |payload: Vec<u8>| String::from_utf8(payload).map_err(|_| "Frame payload is not valid UTF-8".to_string());
+        match frame_type {
+            FRAME_HELLO => Ok(Frame::Hello(payload)),
+            FRAME_START => StartConfig::decode(&text(payload)?).map(Frame::Start),
+            FRAME_READY => text(payload).map(Frame::Ready),
+            FRAME_AUDIO => Ok(Frame::Audio(payload)),
+            FRAME_END => Ok(Frame::End),
+            FRAME_RESULT => text(payload).map(Frame::Result),
+            FRAME_ERROR => ServerError::parse_payload(&text(payload)?)
+                .map(Frame::Error)
+                .ok_or("Invalid error frame".to_string()),
+            _ => Err(format!("Unknown frame type: {:#04x}", frame_type)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Frame::Hello(_) => "Hello",
+            Frame::Start(_) => "Start",
+            Frame::Ready(_) => "Ready",
+            Frame::Audio(_) => "Audio",
+            Frame::End => "End",
+            Frame::Result(_) => "Result",
+            Frame::Error(_) => "Error",
+        }
+    }
+}
+
+/// 从字节流中切出完整的帧，一个帧可能被拆到多次读取中
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// 取出下一个完整的帧，数据不足时返回 `None`
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+        if length > MAX_FRAME_SIZE {
+            return Err(format!("Frame too large: {} bytes, at most {}", length, MAX_FRAME_SIZE));
+        }
+        if self.buffer.len() < FRAME_HEADER_SIZE + length {
+            return Ok(None);
+        }
+        let frame_type = self.buffer[0];
+        let payload = self.buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length].to_vec();
+        self.buffer.drain(..FRAME_HEADER_SIZE + length);
+        Frame::decode(frame_type, payload).map(Some)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+/// 读取下一个帧，对端关闭连接时返回 `None`。
+///
+/// 未读完的数据保存在 `decoder` 中，所以在 `select!` 中被取消也不会丢数据。
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, decoder: &mut FrameDecoder) -> Result<Option<Frame>, String> {
+    let mut buf = [0; 4096];
+    loop {
+        if let Some(frame) = decoder.next_frame()? {
+            return Ok(Some(frame));
+        }
+        let n = reader.read(&mut buf).await.map_err(|e| format!("Reading from stream failed: {}", e))?;
+        if n == 0 {
+            return if decoder.is_empty() {
+                Ok(None)
+            } else {
+                Err("Stream closed in the middle of a frame".to_string())
+            };
+        }
+        decoder.push(&buf[..n]);
+    }
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &Frame) -> std::io::Result<()> {
+    writer.write_all(&frame.encode()).await?;
+    writer.flush().await
+}
+
+/// 在客户端和服务端都支持的版本中选最高的一个
+pub fn negotiate(versions: &[u8]) -> Option<u8> {
+    versions.iter().copied().filter(|version| SUPPORTED_VERSIONS.contains(version)).max()
+}
+
+/// Start 帧声明的会话参数，负载是每行一个的 `key=value`。
+///
+/// 没有给出的音频参数使用服务端的默认配置；`model`、`language` 给出时必须与服务端加载的模型一致。
+/// 服务端开启认证时 `auth` 是客户端的密钥或令牌。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StartConfig {
+    pub container: Option<AudioContainer>,
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    pub channels: Option<usize>,
+    pub channel_mode: Option<ChannelMode>,
+    pub channel_labels: Option<Vec<String>>,
+    pub result_format: Option<ResultFormat>,
+    pub model: Option<String>,
+    pub language: Option<String>,
+    pub auth: Option<String>,
+}
+
+impl StartConfig {
+    pub fn encode(&self) -> String {
+        let mut fields = Vec::new();
+        if let Some(container) = self.container {
+            fields.push(format!("container={}", container.as_str()));
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            fields.push(format!("sample_rate={}", sample_rate));
+        }
+        if let Some(sample_format) = self.sample_format {
+            fields.push(format!("sample_format={}", sample_format.as_str()));
+        }
+        if let Some(channels) = self.channels {
+            fields.push(format!("channels={}", channels));
+        }
+        if let Some(channel_mode) = self.channel_mode {
+            fields.push(format!("channel_mode={}", channel_mode.as_str()));
+        }
+        if let Some(channel_labels) = &self.channel_labels {
+            fields.push(format!("channel_labels={}", channel_labels.join(",")));
+        }
+        if let Some(result_format) = self.result_format {
+            fields.push(format!("result_format={}", result_format.as_str()));
+        }
+        if let Some(model) = &self.model {
+            fields.push(format!("model={}", model));
+        }
+        if let Some(language) = &self.language {
+            fields.push(format!("language={}", language));
+        }
+        if let Some(auth) = &self.auth {
+            fields.push(format!("auth={}", auth));
+        }
+        fields.join("\n")
+    }
+
+    pub fn decode(payload: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        for line in payload.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once('=').ok_or(format!("Invalid start field: {}", line))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "container" => config.container = Some(parse_field(key, value)?),
+                "sample_rate" => config.sample_rate = Some(parse_field(key, value)?),
+                "sample_format" => config.sample_format = Some(parse_field(key, value)?),
+                "channels" => config.channels = Some(parse_field(key, value)?),
+                "channel_mode" => config.channel_mode = Some(parse_field(key, value)?),
+                "channel_labels" => config.channel_labels = Some(value.split(',').map(|label| label.trim().to_string()).collect()),
+                "result_format" => config.result_format = Some(parse_field(key, value)?),
+                "model" => config.model = Some(value.to_string()),
+                "language" => config.language = Some(value.to_string()),
+                "auth" => config.auth = Some(value.to_string()),
+                _ => return Err(format!("Unknown start field: {}", key)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// 在服务端默认配置上应用本次会话声明的参数
+    pub fn audio_config(&self, defaults: &AudioConfig) -> Result<AudioConfig, String> {
+        let mut config = defaults.clone();
+        if let Some(container) = self.container {
+            config.container = container;
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            if sample_rate == 0 {
+                return Err("Invalid value for sample_rate: 0".to_string());
+            }
+            config.sample_rate = sample_rate;
+        }
+        if let Some(sample_format) = self.sample_format {
+            config.sample_format = sample_format;
+        }
+        if let Some(channels) = self.channels {
+            check_channels(channels)?;
+            config.channels = channels;
+        }
+        if let Some(channel_mode) = self.channel_mode {
+            config.channel_mode = channel_mode;
+        }
+        if let Some(channel_labels) = &self.channel_labels {
+            if let Some(label) = channel_labels.iter().find(|label| !is_valid_label(label)) {
+                return Err(format!("Invalid value for channel_labels: {:?}", label));
+            }
+            config.channel_labels = channel_labels.clone();
+        }
+        Ok(config)
+    }
+
+    /// 校验模型和语言，生成本次会话的配置
+    pub fn session_config(&self, defaults: &SessionDefaults) -> Result<SessionConfig, ServerError> {
+        if let Some(model) = self.model.as_ref().filter(|model| **model != defaults.model) {
+            return Err(ServerError::new(ErrorCode::UnsupportedModel, format!("Unknown model: {}, available: {}", model, defaults.model)));
+        }
+        if let Some(language) = self.language.as_ref().filter(|language| **language != defaults.language) {
+            return Err(ServerError::new(ErrorCode::UnsupportedModel, format!("Unsupported language: {}, available: {}", language, defaults.language)));
+        }
+        Ok(SessionConfig {
+            audio_config: self.audio_config(&defaults.audio_config).map_err(|e| ServerError::new(ErrorCode::InvalidAudio, e))?,
+            result_format: self.result_format.unwrap_or(defaults.result_format),
+        })
+    }
+}
+
+fn parse_field<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", key, value))
+}
+
+/// 服务端返回给客户端的错误类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// 没有空闲的通道
+    Busy,
+    /// 音频格式不支持或数据无法解析
+    InvalidAudio,
+    /// 识别器出错
+    RecognizerError,
+    /// 等待客户端数据超时
+    Timeout,
+    /// 没有双方都支持的协议版本
+    UnsupportedVersion,
+    /// 请求的模型或语言与服务端加载的不一致
+    UnsupportedModel,
+    /// 帧的类型、顺序或内容不合协议
+    InvalidRequest,
+    /// 缺少凭证或凭证无效
+    Unauthorized,
+    /// 超过了密钥的并发会话数或每日音频时长
+    QuotaExceeded,
+}
+
+impl FromStr for ErrorCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "busy" => Ok(ErrorCode::Busy),
+            "invalid_audio" => Ok(ErrorCode::InvalidAudio),
+            "recognizer_error" => Ok(ErrorCode::RecognizerError),
+            "timeout" => Ok(ErrorCode::Timeout),
+            "unsupported_version" => Ok(ErrorCode::UnsupportedVersion),
+            "unsupported_model" => Ok(ErrorCode::UnsupportedModel),
+            "invalid_request" => Ok(ErrorCode::InvalidRequest),
+            "unauthorized" => Ok(ErrorCode::Unauthorized),
+            "quota_exceeded" => Ok(ErrorCode::QuotaExceeded),
+            _ => Err(format!("Unknown error code: {}", s)),
+        }
+    }
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Busy => "busy",
+            ErrorCode::InvalidAudio => "invalid_audio",
+            ErrorCode::RecognizerError => "recognizer_error",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::UnsupportedVersion => "unsupported_version",
+            ErrorCode::UnsupportedModel => "unsupported_model",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+        }
+    }
+}
+
+/// 带错误码的错误消息。
+///
+/// 分帧协议下是 `Error` 帧，负载为 `<code> <message>`；裸协议下是一行 `error <code> <message>`，
+/// JSON 格式时是 `{"type":"error","session_id":...,"code":...,"message":...}`。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+// JSON 格式的错误消息
+#[derive(Serialize, Deserialize)]
+struct JsonError {
+    #[serde(rename = "type")]
+    kind: String,
+    session_id: String,
+    code: String,
+    message: String,
+}
+
+impl ServerError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    pub fn to_payload(&self) -> String {
+        format!("{} {}", self.code.as_str(), self.message)
+    }
+
+    pub fn parse_payload(payload: &str) -> Option<Self> {
+        let (code, message) = payload.split_once(' ').unwrap_or((payload, ""));
+        Some(Self::new(code.parse().ok()?, message))
+    }
+
+    pub fn to_line(&self) -> String {
+        format!("error {}\n", self.to_payload())
+    }
+
+    pub fn to_json(&self, session_id: &str) -> String {
+        let error = JsonError {
+            kind: "error".to_string(),
+            session_id: session_id.to_string(),
+            code: self.code.as_str().to_string(),
+            message: self.message.clone(),
+        };
+        format!("{}\n", serde_json::to_string(&error).unwrap_or_default())
+    }
+
+    pub fn to_format(&self, format: ResultFormat, session_id: &str) -> String {
+        match format {
+            ResultFormat::Text => self.to_line(),
+            ResultFormat::Json => self.to_json(session_id),
+        }
+    }
+
+    /// 解析裸协议下的一行，文本和 JSON 格式都可以，不是错误消息时返回 `None`
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.starts_with('{') {
+            let error: JsonError = serde_json::from_str(line).ok()?;
+            if error.kind != "error" {
+                return None;
+            }
+            return Some(Self::new(error.code.parse().ok()?, error.message));
+        }
+        Self::parse_payload(line.strip_prefix("error ")?)
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+/// 客户端握手失败的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakeError {
+    /// 服务端返回了错误
+    Rejected(ServerError),
+    /// 连接或数据出错
+    Failed(String),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Rejected(error) => write!(f, "Rejected by server: {}", error),
+            HandshakeError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// 一次会话实际使用的配置
+#[derive(Debug, Clone, Default)]
+pub struct SessionConfig {
+    pub audio_config: AudioConfig,
+    pub result_format: ResultFormat,
+}
+
+/// 服务端握手时用来核对 Start 帧的配置
+#[derive(Debug, Clone)]
+pub struct SessionDefaults {
+    pub audio_config: AudioConfig,
+    pub result_format: ResultFormat,
+    pub model: String,
+    pub language: String,
+}
+
+/// 服务端握手：协商版本并读取 Start 帧，返回本次会话的配置和客户端的凭证。
+///
+/// 出错时由调用方向客户端发送 `Error` 帧。
+pub async fn accept_session<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, decoder: &mut FrameDecoder,
+    defaults: &SessionDefaults) -> Result<(SessionConfig, Option<String>), ServerError> {
+    let invalid = |message: String| ServerError::new(ErrorCode::InvalidRequest, message);
+    let version = match read_frame(stream, decoder).await.map_err(invalid)? {
+        Some(Frame::Hello(versions)) => negotiate(&versions).ok_or(ServerError::new(ErrorCode::UnsupportedVersion,
+            format!("Unsupported protocol versions: {:?}, supported: {:?}", versions, SUPPORTED_VERSIONS)))?,
+        Some(frame) => return Err(invalid(format!("Expected Hello frame, got {}", frame.name()))),
+        None => return Err(invalid("Connection closed before handshake".to_string())),
+    };
+    write_frame(stream, &Frame::Hello(vec![version])).await.map_err(|e| invalid(e.to_string()))?;
+    let start = match read_frame(stream, decoder).await.map_err(invalid)? {
+        Some(Frame::Start(start)) => start,
+        Some(frame) => return Err(invalid(format!("Expected Start frame, got {}", frame.name()))),
+        None => return Err(invalid("Connection closed before Start frame".to_string())),
+    };
+    Ok((start.session_config(defaults)?, start.auth))
+}
+
+/// 客户端握手：发送支持的版本和 Start 帧，等到服务端回复 `Ready`，返回协商的版本和会话 ID
+pub async fn start_session<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, decoder: &mut FrameDecoder,
+    start: &StartConfig) -> Result<(u8, String), HandshakeError> {
+    write_frame(stream, &Frame::Hello(SUPPORTED_VERSIONS.to_vec())).await.map_err(|e| HandshakeError::Failed(e.to_string()))?;
+    let version = match read_frame(stream, decoder).await.map_err(HandshakeError::Failed)? {
+        Some(Frame::Hello(version)) if version.len() == 1 && SUPPORTED_VERSIONS.contains(&version[0]) => version[0],
+        Some(Frame::Error(error)) => return Err(HandshakeError::Rejected(error)),
+        Some(frame) => return Err(HandshakeError::Failed(format!("Unexpected {} frame during handshake", frame.name()))),
+        None => return Err(HandshakeError::Failed("Connection closed during handshake".to_string())),
+    };
+    write_frame(stream, &Frame::Start(start.clone())).await.map_err(|e| HandshakeError::Failed(e.to_string()))?;
+    match read_frame(stream, decoder).await.map_err(HandshakeError::Failed)? {
+        Some(Frame::Ready(session_id)) => Ok((version, session_id)),
+        Some(Frame::Error(error)) => Err(HandshakeError::Rejected(error)),
+        Some(frame) => Err(HandshakeError::Failed(format!("Unexpected {} frame during handshake", frame.name()))),
+        None => Err(HandshakeError::Failed("Connection closed during handshake".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_split_across_reads_are_reassembled() {
+        let frames = [
+            Frame::Hello(vec![1, 2]),
+            Frame::Audio(vec![0; 300]),
+            Frame::Result("0:1 final hello".to_string()),
+            Frame::End,
+        ];
+        let bytes: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+        for chunk in [1, 3, 7, 64] {
+            let mut decoder = FrameDecoder::default();
+            let mut decoded = Vec::new();
+            for chunk in bytes.chunks(chunk) {
+                decoder.push(chunk);
+                while let Some(frame) = decoder.next_frame().unwrap() {
+                    decoded.push(frame);
+                }
+            }
+            assert_eq!(decoded, frames, "chunk {}", chunk);
+            assert!(decoder.is_empty());
+        }
+    }
+
+    #[test]
+    fn rejects_oversize_and_unknown_frames() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&[FRAME_AUDIO]);
+        decoder.push(&(MAX_FRAME_SIZE as u32 + 1).to_be_bytes());
+        assert_eq!(decoder.next_frame(), Err(format!("Frame too large: {} bytes, at most {}", MAX_FRAME_SIZE + 1, MAX_FRAME_SIZE)));
+
+        // 长度正好是上限的帧在数据到齐前只是等待
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&[FRAME_AUDIO]);
+        decoder.push(&(MAX_FRAME_SIZE as u32).to_be_bytes());
+        assert_eq!(decoder.next_frame(), Ok(None));
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&[0x42, 0, 0, 0, 1, 0]);
+        assert_eq!(decoder.next_frame(), Err("Unknown frame type: 0x42".to_string()));
+    }
+
+    #[test]
+    fn start_config_round_trips() {
+        let config = StartConfig {
+            container: Some(AudioContainer::Wav),
+            sample_rate: Some(8000),
+            sample_format: Some(SampleFormat::MuLaw),
+            channels: Some(2),
+            channel_mode: Some(ChannelMode::Separate),
+            channel_labels: Some(vec!["agent".to_string(), "customer".to_string()]),
+            result_format: Some(ResultFormat::Json),
+            model: Some("zipformer".to_string()),
+            language: Some("zh".to_string()),
+            auth: Some("key.secret".to_string()),
+        };
+        assert_eq!(StartConfig::decode(&config.encode()), Ok(config.clone()));
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&Frame::Start(config.clone()).encode());
+        assert_eq!(decoder.next_frame(), Ok(Some(Frame::Start(config))));
+        assert_eq!(StartConfig::decode(""), Ok(StartConfig::default()));
+        assert_eq!(StartConfig::decode("speed=2"), Err("Unknown start field: speed".to_string()));
+        assert_eq!(StartConfig::decode("channels=many"), Err("Invalid value for channels: many".to_string()));
+    }
+
+    #[test]
+    fn formats_server_errors() {
+        let error = ServerError::new(ErrorCode::QuotaExceeded, "Daily audio quota exhausted");
+        assert_eq!(error.to_payload(), "quota_exceeded Daily audio quota exhausted");
+        assert_eq!(error.to_line(), "error quota_exceeded Daily audio quota exhausted\n");
+        assert_eq!(error.to_json("s1"), "{\"type\":\"error\",\"session_id\":\"s1\",\"code\":\"quota_exceeded\",\"message\":\"Daily audio quota exhausted\"}\n");
+        assert_eq!(error.to_string(), "quota_exceeded: Daily audio quota exhausted");
+        assert_eq!(ServerError::parse_payload(&error.to_payload()), Some(error.clone()));
+        assert_eq!(ServerError::parse_line(&error.to_line()), Some(error.clone()));
+        assert_eq!(ServerError::parse_line(&error.to_json("s1")), Some(error.clone()));
+        assert_eq!(ServerError::parse_payload("busy"), Some(ServerError::new(ErrorCode::Busy, "")));
+        assert_eq!(ServerError::parse_payload("nonsense message"), None);
+        assert_eq!(ServerError::parse_line("0:1 final hello"), None);
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&Frame::Error(error.clone()).encode());
+        assert_eq!(decoder.next_frame(), Ok(Some(Frame::Error(error))));
+    }
+}
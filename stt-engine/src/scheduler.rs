@@ -0,0 +1,166 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::sherpa::{env_override, RecognitionResult, Recognizer, RecognizerStream};
+
+/// 解码线程池配置，`queue_capacity` 是每个解码线程的请求队列长度，队列满时会话会异步等待。
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub workers: usize,
+    pub batch_size: usize,
+    pub queue_capacity: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            batch_size: 20,
+            queue_capacity: 64,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// 在默认配置的基础上读取 `STT_DECODE_WORKERS`、`STT_DECODE_BATCH_SIZE`、`STT_DECODE_QUEUE_CAPACITY`。
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Self::default();
+        env_override("STT_DECODE_WORKERS", &mut config.workers)?;
+        env_override("STT_DECODE_BATCH_SIZE", &mut config.batch_size)?;
+        env_override("STT_DECODE_QUEUE_CAPACITY", &mut config.queue_capacity)?;
+        Ok(config)
+    }
+}
+
+enum DecodeTask {
+    Transcribe(Vec<f32>),
+    Finish,
+}
+
+struct DecodeRequest<S> {
+    stream: Arc<S>,
+    task: DecodeTask,
+    reply: oneshot::Sender<Result<RecognitionResult, String>>,
+}
+
+/// 解码调度器，持有一组独立的解码线程，识别相关的 FFI 调用都在这些线程上完成，
+/// 不占用 tokio 的工作线程。
+///
+/// 每个会话固定绑定到一个解码线程，解码线程收集绑定在它上面的所有会话送来的音频，
+/// 把已就绪的流分批做多流解码，再把结果分别回传给各个会话。
+pub struct DecodeScheduler<R: Recognizer> {
+    workers: Vec<mpsc::Sender<DecodeRequest<R::Stream>>>,
+    next_worker: AtomicUsize,
+}
+
+impl<R: Recognizer> DecodeScheduler<R> {
+    pub fn start(recognizer: Arc<R>, config: &SchedulerConfig) -> Result<Self, String> {
+        let batch_size = config.batch_size.max(1);
+        let mut workers = Vec::new();
+        for i in 0..config.workers.max(1) {
+            let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+            let recognizer = recognizer.clone();
+            thread::Builder::new()
+                .name(format!("decode-worker-{}", i))
+                .spawn(move || Self::run(recognizer, batch_size, rx))
+                .map_err(|e| format!("Failed to start decode thread: {}", e))?;
+            workers.push(tx);
+        }
+        Ok(Self { workers, next_worker: AtomicUsize::new(0) })
+    }
+
+    /// 为一个识别流分配解码线程，之后该流的所有请求都由同一个线程处理
+    pub fn session(&self, stream: Arc<R::Stream>) -> DecodeSession<R> {
+        let worker = self.next_worker.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+        DecodeSession {
+            stream,
+            sender: self.workers[worker].clone(),
+        }
+    }
+
+    fn run(recognizer: Arc<R>, batch_size: usize, mut rx: mpsc::Receiver<DecodeRequest<R::Stream>>) {
+        while let Some(request) = rx.blocking_recv() {
+            // 取出当前排队的全部请求，同一轮内一起解码
+            let mut requests = vec![request];
+            while let Ok(request) = rx.try_recv() {
+                requests.push(request);
+            }
+
+            let mut results = requests.iter().map(|request| match &request.task {
+                DecodeTask::Transcribe(samples) => request.stream.accept_waveform(samples),
+                DecodeTask::Finish => request.stream.input_finished(),
+            }).collect::<Vec<_>>();
+
+            loop {
+                // 同一个流在一轮里可能有多个请求，解码时只能出现一次
+                let mut ready: Vec<&Arc<R::Stream>> = Vec::new();
+                for (request, result) in requests.iter().zip(results.iter()) {
+                    if result.is_ok() && request.stream.is_ready() && !ready.iter().any(|s| Arc::ptr_eq(s, &request.stream)) {
+                        ready.push(&request.stream);
+                    }
+                }
+                if ready.is_empty() {
+                    break;
+                }
+                let ready = ready.iter().map(|s| s.as_ref()).collect::<Vec<_>>();
+                for batch in ready.chunks(batch_size) {
+                    if let Err(e) = recognizer.decode_batch(batch) {
+                        eprintln!("Error decoding batch: {}", e);
+                        for (request, result) in requests.iter().zip(results.iter_mut()) {
+                            if batch.iter().any(|s| std::ptr::eq(*s, request.stream.as_ref())) {
+                                *result = Err(e.clone());
+                            }
+                        }
+                    }
+                }
+            }
+
+            for (request, result) in requests.into_iter().zip(results) {
+                let result = result.and_then(|_| match request.task {
+                    // 检测到端点时清空该句的解码状态，之后的音频属于下一句
+                    DecodeTask::Transcribe(_) => request.stream.get_result().and_then(|result| if result.is_final {
+                        request.stream.next_segment().map(|_| result)
+                    } else {
+                        Ok(result)
+                    }),
+                    // 输入已结束，当前句无论是否检测到端点都作为最终结果返回
+                    DecodeTask::Finish => request.stream.get_result().map(|mut result| {
+                        result.is_final = true;
+                        result
+                    }),
+                });
+                let _ = request.reply.send(result);
+            }
+        }
+    }
+}
+
+/// 绑定到某个解码线程的识别流
+pub struct DecodeSession<R: Recognizer> {
+    stream: Arc<R::Stream>,
+    sender: mpsc::Sender<DecodeRequest<R::Stream>>,
+}
+
+impl<R: Recognizer> DecodeSession<R> {
+    pub fn stream(&self) -> &Arc<R::Stream> {
+        &self.stream
+    }
+
+    pub async fn transcribe(&self, samples: Vec<f32>) -> Result<RecognitionResult, String> {
+        self.submit(DecodeTask::Transcribe(samples)).await
+    }
+
+    /// 结束输入并取得最后一句的结果，之后识别流不能再接受音频
+    pub async fn finish(&self) -> Result<RecognitionResult, String> {
+        self.submit(DecodeTask::Finish).await
+    }
+
+    async fn submit(&self, task: DecodeTask) -> Result<RecognitionResult, String> {
+        let (reply, result) = oneshot::channel();
+        // 队列已满时在这里异步等待，不会阻塞 tokio 工作线程
+        self.sender.send(DecodeRequest { stream: self.stream.clone(), task, reply }).await
+            .map_err(|_| "Decode scheduler is stopped".to_string())?;
+        result.await.map_err(|_| "Decode scheduler dropped the request".to_string())?
+    }
+}
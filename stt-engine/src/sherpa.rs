@@ -1,86 +1,589 @@
+use std::{ffi::{CStr, CString}, os::raw::{c_char, c_float, c_int}, path::Path, str::FromStr, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}};
+
+/// 识别器抽象，整个服务端共享一个识别器（即一份模型），每个会话通过 `create_stream`
+/// 获得自己的识别流。
+///
+/// `Sherpa` 通过 sherpa-bridge 调用 sherpa-onnx，`MockRecognizer` 不依赖模型文件，
+/// 可以在没有模型的环境中运行服务端。
+pub trait Recognizer: Send + Sync + Sized + 'static {
+    type Stream: RecognizerStream;
+
+    fn create(config: &ModelConfig) -> Result<Self, String>;
+    fn create_stream(recognizer: &Arc<Self>) -> Result<Self::Stream, String>;
+
+    /// 识别流接受的采样率，其他采样率的音频需要先重采样
+    fn sample_rate(&self) -> u32;
+
+    /// 对一批已就绪的流各解码一次，默认逐个解码，支持多流解码的实现可以一次完成
+    fn decode_batch(&self, streams: &[&Self::Stream]) -> Result<(), String> {
+        for stream in streams {
+            stream.decode()?;
+        }
+        Ok(())
+    }
+}
+
+/// 识别结果，`segment` 是当前会话内的语句序号，`is_final` 表示检测到端点、该句已经结束，
+/// 之后的结果属于下一句。`timestamps` 与 `tokens` 一一对应，单位为秒，模型不支持时为空。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecognitionResult {
+    pub text: String,
+    pub tokens: Vec<String>,
+    pub timestamps: Vec<f32>,
+    pub is_final: bool,
+    pub segment: usize,
+}
+
+/// 单个会话的识别流，只保存解码状态，模型由所属的 `Recognizer` 持有。
+///
+/// `input_finished` 之后识别流不能再接受音频，每个会话都要新建识别流。
+pub trait RecognizerStream: Send + Sync + 'static {
+    fn accept_waveform(&self, samples: &[f32]) -> Result<(), String>;
+    /// 送入尾部静音并标记输入结束，之后需要继续解码到流不再就绪
+    fn input_finished(&self) -> Result<(), String>;
+    fn is_ready(&self) -> bool;
+    fn decode(&self) -> Result<(), String>;
+    /// 获取当前识别结果，不改变解码状态
+    fn get_result(&self) -> Result<RecognitionResult, String>;
+    /// 结果检测到端点后调用，清空当前句的解码状态，开始新的一句
+    fn next_segment(&self) -> Result<(), String>;
+    fn close(&self) -> Result<(), String>;
+
+    fn transcribe(&self, samples: &[f32]) -> Result<RecognitionResult, String> {
+        self.accept_waveform(samples)?;
+        while self.is_ready() {
+            self.decode()?;
+        }
+        let result = self.get_result()?;
+        if result.is_final {
+            self.next_segment()?;
+        }
+        Ok(result)
+    }
+}
+
+/// 模型及解码参数，各模型文件路径如果是相对路径则相对于 `model_dir`。
+///
+/// `name` 和 `language` 只用于会话握手时核对客户端请求的模型和语言，不传给 sherpa-onnx。
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    pub name: String,
+    pub language: String,
+    pub model_dir: String,
+    pub tokens: String,
+    pub encoder: String,
+    pub decoder: String,
+    pub joiner: String,
+    pub num_threads: i32,
+    pub provider: String,
+    pub decoding_method: String,
+    pub max_active_paths: i32,
+    pub sample_rate: i32,
+    pub feature_dim: i32,
+    pub enable_endpoint: bool,
+    pub rule1_min_trailing_silence: f32,
+    pub rule2_min_trailing_silence: f32,
+    pub rule3_min_utterance_length: f32,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            name: "zipformer-multi-zh-hans".to_string(),
+            language: "zh".to_string(),
+            model_dir: "../sherpa/sherpa-models".to_string(),
+            tokens: "tokens.txt".to_string(),
+            encoder: "encoder-epoch-20-avg-1-chunk-16-left-128.onnx".to_string(),
+            decoder: "decoder-epoch-20-avg-1-chunk-16-left-128.onnx".to_string(),
+            joiner: "joiner-epoch-20-avg-1-chunk-16-left-128.onnx".to_string(),
+            num_threads: 1,
+            provider: "cpu".to_string(),
+            decoding_method: "greedy_search".to_string(),
+            max_active_paths: 4,
+            sample_rate: 16000,
+            feature_dim: 80,
+            enable_endpoint: true,
+            rule1_min_trailing_silence: 2.4,
+            rule2_min_trailing_silence: 1.2,
+            rule3_min_utterance_length: 300.0,
+        }
+    }
+}
+
+impl ModelConfig {
+    /// 在默认配置的基础上读取 `STT_*` 环境变量，例如 `STT_MODEL_DIR`、`STT_NUM_THREADS`。
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Self::default();
+        env_override("STT_MODEL_NAME", &mut config.name)?;
+        env_override("STT_LANGUAGE", &mut config.language)?;
+        env_override("STT_MODEL_DIR", &mut config.model_dir)?;
+        env_override("STT_TOKENS", &mut config.tokens)?;
+        env_override("STT_ENCODER", &mut config.encoder)?;
+        env_override("STT_DECODER", &mut config.decoder)?;
+        env_override("STT_JOINER", &mut config.joiner)?;
+        env_override("STT_NUM_THREADS", &mut config.num_threads)?;
+        env_override("STT_PROVIDER", &mut config.provider)?;
+        env_override("STT_DECODING_METHOD", &mut config.decoding_method)?;
+        env_override("STT_MAX_ACTIVE_PATHS", &mut config.max_active_paths)?;
+        env_override("STT_SAMPLE_RATE", &mut config.sample_rate)?;
+        env_override("STT_FEATURE_DIM", &mut config.feature_dim)?;
+        env_override("STT_ENABLE_ENDPOINT", &mut config.enable_endpoint)?;
+        env_override("STT_RULE1_MIN_TRAILING_SILENCE", &mut config.rule1_min_trailing_silence)?;
+        env_override("STT_RULE2_MIN_TRAILING_SILENCE", &mut config.rule2_min_trailing_silence)?;
+        env_override("STT_RULE3_MIN_UTTERANCE_LENGTH", &mut config.rule3_min_utterance_length)?;
+        Ok(config)
+    }
+
+    pub fn tokens_path(&self) -> String {
+        self.resolve(&self.tokens)
+    }
+
+    pub fn encoder_path(&self) -> String {
+        self.resolve(&self.encoder)
+    }
+
+    pub fn decoder_path(&self) -> String {
+        self.resolve(&self.decoder)
+    }
+
+    pub fn joiner_path(&self) -> String {
+        self.resolve(&self.joiner)
+    }
+
+    fn resolve(&self, file: &str) -> String {
+        let path = Path::new(file);
+        if path.is_absolute() || self.model_dir.is_empty() {
+            file.to_string()
+        } else {
+            Path::new(&self.model_dir).join(path).to_string_lossy().to_string()
+        }
+    }
+}
+
+pub(crate) fn env_override<T: FromStr>(key: &str, value: &mut T) -> Result<(), String> {
+    if let Ok(raw) = std::env::var(key) {
+        *value = raw.trim().parse().map_err(|_| format!("Invalid value for {}: {}", key, raw))?;
+    }
+    Ok(())
+}
+
+#[repr(C)]
+struct SherpaModelConfig {
+    tokens: *const c_char,
+    encoder: *const c_char,
+    decoder: *const c_char,
+    joiner: *const c_char,
+    num_threads: c_int,
+    provider: *const c_char,
+    decoding_method: *const c_char,
+    max_active_paths: c_int,
+    sample_rate: c_int,
+    feature_dim: c_int,
+    enable_endpoint: c_int,
+    rule1_min_trailing_silence: c_float,
+    rule2_min_trailing_silence: c_float,
+    rule3_min_utterance_length: c_float,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 struct SherpaHandle {
     pub recognizer: *const (),
     pub stream: *const (),
+    pub sample_rate: c_int,
 }
 
 unsafe impl Send for SherpaHandle { }
 unsafe impl Sync for SherpaHandle { }
 
+#[repr(C)]
+struct SherpaResult {
+    text: *const c_char,
+    tokens: *const *const c_char,
+    timestamps: *const c_float,
+    count: c_int,
+    is_endpoint: c_int,
+    _impl: *const (),
+}
+
+impl SherpaResult {
+    /// 把 C 侧的结果拷贝出来，调用方随后负责释放 C 侧的内存
+    unsafe fn to_result(&self, segment: usize) -> RecognitionResult {
+        let to_string = |ptr: *const c_char| if ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(ptr).to_string_lossy().to_string()
+        };
+        let count = self.count.max(0) as usize;
+        let tokens = if self.tokens.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(self.tokens, count).iter().map(|token| to_string(*token)).collect()
+        };
+        let timestamps = if self.timestamps.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(self.timestamps, count).to_vec()
+        };
+        RecognitionResult {
+            text: to_string(self.text),
+            tokens,
+            timestamps,
+            is_final: self.is_endpoint != 0,
+            segment,
+        }
+    }
+}
+
 #[link(name = "sherpa-bridge")]
 extern "C" {
-    fn sherpa_init(
-        tokens: *const ::std::os::raw::c_char,
-        encoder: *const ::std::os::raw::c_char,
-        decoder: *const ::std::os::raw::c_char,
-        joiner: *const ::std::os::raw::c_char) -> SherpaHandle;
-    fn sherpa_transcribe(
-            handle: SherpaHandle,
-            result: *mut ::std::os::raw::c_char,
-            samples: *const f32,
-            len: ::std::os::raw::c_int);
-    fn sherpa_reset(handle: SherpaHandle);
+    fn sherpa_create_recognizer(config: *const SherpaModelConfig) -> *const ();
+    fn sherpa_destroy_recognizer(recognizer: *const ());
+    fn sherpa_create_stream(recognizer: *const (), sample_rate: c_int) -> SherpaHandle;
+    fn sherpa_accept_waveform(handle: SherpaHandle, samples: *const f32, len: c_int);
+    fn sherpa_input_finished(handle: SherpaHandle);
+    fn sherpa_is_ready(handle: SherpaHandle) -> c_int;
+    fn sherpa_decode_streams(recognizer: *const (), handles: *const SherpaHandle, n: c_int);
+    fn sherpa_get_result(handle: SherpaHandle) -> SherpaResult;
+    fn sherpa_destroy_result(result: SherpaResult);
+    fn sherpa_reset_stream(handle: SherpaHandle);
     fn sherpa_close(handle: SherpaHandle);
 }
 
-#[repr(C)]
 #[derive(Debug)]
-pub(crate) struct Sherpa {
-    handle: Option<SherpaHandle>,
+pub struct Sherpa {
+    recognizer: *const (),
+    sample_rate: i32,
 }
 
+unsafe impl Send for Sherpa { }
+unsafe impl Sync for Sherpa { }
+
 impl Sherpa {
     pub(crate) fn new() -> Self {
-        Self { handle: None }
+        Self { recognizer: std::ptr::null(), sample_rate: 16000 }
     }
 }
 
 impl Sherpa {
-    pub fn init(&mut self, tokens: &str, encoder: &str, decoder: &str, joiner: &str) {
-        let tokens_cstr = ::std::ffi::CString::new(tokens).unwrap();
-        let encoder_cstr = ::std::ffi::CString::new(encoder).unwrap();
-        let decoder_cstr = ::std::ffi::CString::new(decoder).unwrap();
-        let joiner_cstr = ::std::ffi::CString::new(joiner).unwrap();
-
-        self.handle.replace(unsafe {
-            sherpa_init(tokens_cstr.as_ptr(),
-                encoder_cstr.as_ptr(),
-                decoder_cstr.as_ptr(),
-                joiner_cstr.as_ptr())
-        });
-    }
-
-    pub fn transcribe(&self, samples: &[f32]) -> Result<String, String> {
-        if let Some(handle) = self.handle {
-            let len = samples.len() as i32;
-            let mut result_buf = [0_u8; 2048];
-            let result_ptr = result_buf.as_mut_ptr() as *mut ::std::os::raw::c_char;  
-
-            unsafe { sherpa_transcribe(handle, result_ptr, samples.as_ptr(), len) };
-
-            let c_str = unsafe { ::std::ffi::CStr::from_ptr(result_ptr) };
-            Ok(c_str.to_str().unwrap_or("").to_string())
+    pub fn init(&mut self, config: &ModelConfig) -> Result<(), String> {
+        let to_cstr = |value: String| CString::new(value).map_err(|e| format!("init: {}", e));
+        let tokens_cstr = to_cstr(config.tokens_path())?;
+        let encoder_cstr = to_cstr(config.encoder_path())?;
+        let decoder_cstr = to_cstr(config.decoder_path())?;
+        let joiner_cstr = to_cstr(config.joiner_path())?;
+        let provider_cstr = to_cstr(config.provider.clone())?;
+        let decoding_method_cstr = to_cstr(config.decoding_method.clone())?;
+
+        // CString 需要存活到 sherpa_create_recognizer 返回之后
+        let model_config = SherpaModelConfig {
+            tokens: tokens_cstr.as_ptr(),
+            encoder: encoder_cstr.as_ptr(),
+            decoder: decoder_cstr.as_ptr(),
+            joiner: joiner_cstr.as_ptr(),
+            num_threads: config.num_threads,
+            provider: provider_cstr.as_ptr(),
+            decoding_method: decoding_method_cstr.as_ptr(),
+            max_active_paths: config.max_active_paths,
+            sample_rate: config.sample_rate,
+            feature_dim: config.feature_dim,
+            enable_endpoint: config.enable_endpoint as c_int,
+            rule1_min_trailing_silence: config.rule1_min_trailing_silence,
+            rule2_min_trailing_silence: config.rule2_min_trailing_silence,
+            rule3_min_utterance_length: config.rule3_min_utterance_length,
+        };
+
+        if !self.recognizer.is_null() {
+            unsafe { sherpa_destroy_recognizer(self.recognizer) };
+        }
+        self.recognizer = unsafe { sherpa_create_recognizer(&model_config) };
+        if self.recognizer.is_null() {
+            Err("init: Failed to create sherpa recognizer".to_string())
         } else {
-            Err("transcribe: No handle found".to_string())
+            self.sample_rate = config.sample_rate;
+            Ok(())
         }
     }
+}
 
-    pub fn reset(&self) -> Result<(), String> {
-        if let Some(handle) = self.handle {
-            unsafe { sherpa_reset(handle) };
-            Ok(())
+impl Drop for Sherpa {
+    fn drop(&mut self) {
+        // 所有 SherpaStream 都持有 Arc<Sherpa>，走到这里时流已经全部销毁
+        if !self.recognizer.is_null() {
+            unsafe { sherpa_destroy_recognizer(self.recognizer) };
+        }
+    }
+}
+
+impl Recognizer for Sherpa {
+    type Stream = SherpaStream;
+
+    fn create(config: &ModelConfig) -> Result<Self, String> {
+        let mut sherpa = Sherpa::new();
+        sherpa.init(config)?;
+        Ok(sherpa)
+    }
+
+    fn decode_batch(&self, streams: &[&Self::Stream]) -> Result<(), String> {
+        // 解码结束前一直持有各个流的锁，避免流在解码过程中被 close 释放
+        let guards = streams.iter()
+            .map(|stream| stream.handle.lock().map_err(|e| format!("decode_batch: {}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let handles = guards.iter()
+            .map(|guard| guard.ok_or("decode_batch: No handle found".to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !handles.is_empty() {
+            unsafe { sherpa_decode_streams(self.recognizer, handles.as_ptr(), handles.len() as c_int) };
+        }
+        Ok(())
+    }
+
+    fn create_stream(recognizer: &Arc<Self>) -> Result<Self::Stream, String> {
+        let handle = unsafe { sherpa_create_stream(recognizer.recognizer, recognizer.sample_rate) };
+        if handle.stream.is_null() {
+            Err("create_stream: Failed to create sherpa stream".to_string())
         } else {
-            Err("reset: No handle found".to_string())
+            Ok(SherpaStream {
+                _recognizer: recognizer.clone(),
+                handle: Mutex::new(Some(handle)),
+                segment: AtomicUsize::new(0),
+            })
         }
     }
 
-    pub fn close(&self) -> Result<(), String> {
-        if let Some(handle) = self.handle {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+}
+
+#[derive(Debug)]
+pub struct SherpaStream {
+    _recognizer: Arc<Sherpa>,
+    handle: Mutex<Option<SherpaHandle>>,
+    segment: AtomicUsize,
+}
+
+impl SherpaStream {
+    // 调用期间持有锁，`close` 不会在 FFI 调用的过程中释放流
+    fn with_handle<T>(&self, f: impl FnOnce(SherpaHandle) -> T) -> Result<T, String> {
+        let handle = self.handle.lock().map_err(|e| e.to_string())?;
+        let handle = handle.ok_or("No handle found".to_string())?;
+        Ok(f(handle))
+    }
+}
+
+impl RecognizerStream for SherpaStream {
+    fn accept_waveform(&self, samples: &[f32]) -> Result<(), String> {
+        self.with_handle(|handle| unsafe { sherpa_accept_waveform(handle, samples.as_ptr(), samples.len() as c_int) })
+            .map_err(|e| format!("accept_waveform: {}", e))
+    }
+
+    fn input_finished(&self) -> Result<(), String> {
+        self.with_handle(|handle| unsafe { sherpa_input_finished(handle) })
+            .map_err(|e| format!("input_finished: {}", e))
+    }
+
+    fn is_ready(&self) -> bool {
+        self.with_handle(|handle| unsafe { sherpa_is_ready(handle) != 0 }).unwrap_or(false)
+    }
+
+    fn decode(&self) -> Result<(), String> {
+        self.with_handle(|handle| unsafe { sherpa_decode_streams(handle.recognizer, &handle, 1) })
+            .map_err(|e| format!("decode: {}", e))
+    }
+
+    fn get_result(&self) -> Result<RecognitionResult, String> {
+        let segment = self.segment.load(Ordering::Relaxed);
+        self.with_handle(|handle| unsafe {
+            let raw = sherpa_get_result(handle);
+            let result = raw.to_result(segment);
+            sherpa_destroy_result(raw);
+            result
+        }).map_err(|e| format!("get_result: {}", e))
+    }
+
+    fn next_segment(&self) -> Result<(), String> {
+        self.with_handle(|handle| unsafe { sherpa_reset_stream(handle) })
+            .map_err(|e| format!("next_segment: {}", e))?;
+        self.segment.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), String> {
+        let mut handle = self.handle.lock().map_err(|e| format!("close: {}", e))?;
+        if let Some(handle) = handle.take() {
             unsafe { sherpa_close(handle) };
             Ok(())
         } else {
             Err("close: No handle found".to_string())
         }
     }
-}
\ No newline at end of file
+}
+
+impl Drop for SherpaStream {
+    fn drop(&mut self) {
+        if let Ok(handle) = self.handle.get_mut() {
+            if let Some(handle) = handle.take() {
+                unsafe { sherpa_close(handle) };
+            }
+        }
+    }
+}
+
+/// 确定性的模拟识别器，识别结果只取决于当前语句内累计收到的采样数，例如 `mock 3200`，
+/// 每累计 `segment_samples` 个采样视为检测到一次端点，`next_segment` 之后重新计数。
+#[derive(Debug)]
+pub struct MockRecognizer {
+    streams: AtomicUsize,
+    segment_samples: usize,
+    sample_rate: u32,
+}
+
+impl Default for MockRecognizer {
+    fn default() -> Self {
+        Self::with_segment_samples(16000)
+    }
+}
+
+impl MockRecognizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_segment_samples(segment_samples: usize) -> Self {
+        Self {
+            streams: AtomicUsize::new(0),
+            segment_samples: segment_samples.max(1),
+            sample_rate: 16000,
+        }
+    }
+
+    /// 已经创建的识别流数量
+    pub fn stream_count(&self) -> usize {
+        self.streams.load(Ordering::Relaxed)
+    }
+}
+
+impl Recognizer for MockRecognizer {
+    type Stream = MockStream;
+
+    fn create(config: &ModelConfig) -> Result<Self, String> {
+        Ok(Self {
+            sample_rate: config.sample_rate as u32,
+            ..Self::new()
+        })
+    }
+
+    fn create_stream(recognizer: &Arc<Self>) -> Result<Self::Stream, String> {
+        recognizer.streams.fetch_add(1, Ordering::Relaxed);
+        Ok(MockStream {
+            segment_samples: recognizer.segment_samples,
+            sample_rate: recognizer.sample_rate,
+            state: Mutex::new(MockState::default()),
+        })
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    pending: usize,
+    received: usize,
+    segment: usize,
+    finished: bool,
+}
+
+#[derive(Debug)]
+pub struct MockStream {
+    segment_samples: usize,
+    sample_rate: u32,
+    state: Mutex<MockState>,
+}
+
+impl MockStream {
+    fn state(&self) -> Result<std::sync::MutexGuard<'_, MockState>, String> {
+        self.state.lock().map_err(|e| e.to_string())
+    }
+}
+
+impl RecognizerStream for MockStream {
+    // 和 sherpa-onnx 一样，输入结束后不能再送入音频
+    fn accept_waveform(&self, samples: &[f32]) -> Result<(), String> {
+        let mut state = self.state()?;
+        if state.finished {
+            return Err("accept_waveform: Input already finished".to_string());
+        }
+        state.pending += samples.len();
+        Ok(())
+    }
+
+    fn input_finished(&self) -> Result<(), String> {
+        self.state()?.finished = true;
+        Ok(())
+    }
+
+    fn is_ready(&self) -> bool {
+        self.state().is_ok_and(|state| state.pending > 0)
+    }
+
+    fn decode(&self) -> Result<(), String> {
+        let mut state = self.state()?;
+        state.received += state.pending;
+        state.pending = 0;
+        Ok(())
+    }
+
+    fn get_result(&self) -> Result<RecognitionResult, String> {
+        let state = self.state()?;
+        let mut result = RecognitionResult {
+            segment: state.segment,
+            ..Default::default()
+        };
+        if state.received > 0 {
+            result.text = format!("mock {}", state.received);
+            result.tokens = vec!["mock".to_string(), state.received.to_string()];
+            result.timestamps = vec![0.0, state.received as f32 / self.sample_rate as f32];
+        }
+        result.is_final = state.received >= self.segment_samples;
+        Ok(result)
+    }
+
+    fn next_segment(&self) -> Result<(), String> {
+        let mut state = self.state()?;
+        state.received = 0;
+        state.segment += 1;
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), String> {
+        self.input_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_timestamps_follow_model_sample_rate() {
+        let config = ModelConfig { sample_rate: 8000, ..Default::default() };
+        let recognizer = Arc::new(MockRecognizer::create(&config).unwrap());
+        let stream = MockRecognizer::create_stream(&recognizer).unwrap();
+        let result = stream.transcribe(&[0.0; 4000]).unwrap();
+        assert_eq!(result.text, "mock 4000");
+        assert_eq!(result.timestamps, [0.0, 0.5]);
+    }
+
+    #[test]
+    fn mock_rejects_audio_after_input_finished() {
+        let recognizer = Arc::new(MockRecognizer::new());
+        let stream = MockRecognizer::create_stream(&recognizer).unwrap();
+        stream.transcribe(&[0.0; 100]).unwrap();
+        stream.input_finished().unwrap();
+        assert!(stream.accept_waveform(&[0.0; 100]).is_err());
+        assert_eq!(stream.get_result().unwrap().text, "mock 100");
+    }
+}
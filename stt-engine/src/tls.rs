@@ -0,0 +1,148 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use rustls::{crypto::{ring, CryptoProvider}, pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName}, server::WebPkiClientVerifier, ClientConfig, RootCertStore, ServerConfig};
+use tokio::net::TcpStream;
+use tokio_rustls::{client, TlsAcceptor, TlsConnector};
+
+/// 服务端 TLS 配置，证书链和私钥都是 PEM 文件。
+///
+/// 设置了 `client_ca` 时要求客户端出示由它签发的证书（双向 TLS）。
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl ServerTlsConfig {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self { cert: cert.into(), key: key.into(), client_ca: None }
+    }
+
+    /// 读取 `STT_TLS_CERT`、`STT_TLS_KEY` 和 `STT_TLS_CLIENT_CA`，没有设置证书时返回 `None`
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let (cert, key) = match (std::env::var("STT_TLS_CERT"), std::env::var("STT_TLS_KEY")) {
+            (Ok(cert), Ok(key)) => (cert, key),
+            (Err(_), Err(_)) => return Ok(None),
+            _ => return Err("STT_TLS_CERT and STT_TLS_KEY must be set together".to_string()),
+        };
+        let mut config = Self::new(cert, key);
+        config.client_ca = std::env::var("STT_TLS_CLIENT_CA").ok().map(PathBuf::from);
+        Ok(Some(config))
+    }
+
+    pub fn acceptor(&self) -> Result<TlsAcceptor, String> {
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(path)?), provider)
+                    .build()
+                    .map_err(|e| format!("Invalid client CA {}: {}", path.display(), e))?;
+                builder.with_client_cert_verifier(verifier)
+            },
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(load_certs(&self.cert)?, load_key(&self.key)?)
+            .map_err(|e| format!("Invalid certificate {}: {}", self.cert.display(), e))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// 客户端 TLS 配置，`ca` 是用来校验服务端证书的 PEM 文件，使用公共 CA 签发的证书时指向系统的 CA 包即可。
+///
+/// 服务端要求双向 TLS 时需要同时设置 `cert` 和 `key`。
+#[derive(Debug, Clone)]
+pub struct ClientTlsConfig {
+    pub ca: PathBuf,
+    // 默认用连接的地址校验证书
+    pub server_name: Option<String>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl ClientTlsConfig {
+    pub fn new(ca: impl Into<PathBuf>) -> Self {
+        Self { ca: ca.into(), server_name: None, cert: None, key: None }
+    }
+
+    /// 读取 `STT_TLS_CA`、`STT_TLS_SERVER_NAME`、`STT_TLS_CLIENT_CERT` 和 `STT_TLS_CLIENT_KEY`，没有设置 CA 时返回 `None`
+    pub fn from_env() -> Option<Self> {
+        let mut config = Self::new(std::env::var("STT_TLS_CA").ok()?);
+        config.server_name = std::env::var("STT_TLS_SERVER_NAME").ok();
+        config.cert = std::env::var("STT_TLS_CLIENT_CERT").ok().map(PathBuf::from);
+        config.key = std::env::var("STT_TLS_CLIENT_KEY").ok().map(PathBuf::from);
+        Some(config)
+    }
+
+    pub fn connector(&self) -> Result<TlsClient, String> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(load_roots(&self.ca)?);
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| format!("Invalid client certificate {}: {}", cert.display(), e))?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err("Client certificate and key must be set together".to_string()),
+        };
+        let server_name = match &self.server_name {
+            Some(name) => Some(ServerName::try_from(name.clone()).map_err(|e| format!("Invalid server name {}: {}", name, e))?),
+            None => None,
+        };
+        Ok(TlsClient { connector: TlsConnector::from(Arc::new(config)), server_name })
+    }
+}
+
+/// 加载好证书的客户端，多个连接可以共用
+#[derive(Clone)]
+pub struct TlsClient {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl std::fmt::Debug for TlsClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsClient").field("server_name", &self.server_name).finish_non_exhaustive()
+    }
+}
+
+impl TlsClient {
+    pub async fn connect(&self, host: &str, stream: TcpStream) -> std::io::Result<client::TlsStream<TcpStream>> {
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => ServerName::try_from(host.to_string())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        };
+        self.connector.connect(server_name, stream).await
+    }
+}
+
+// 只启用了 ring，显式指定避免依赖进程级的默认实现
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| format!("Failed to read private key from {}: {}", path.display(), e))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    Ok(roots)
+}
@@ -0,0 +1,407 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sherpa::RecognitionResult;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SegmentKind {
+    #[default]
+    Partial,
+    Final,
+}
+
+impl SegmentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SegmentKind::Partial => "partial",
+            SegmentKind::Final => "final",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "partial" => Some(SegmentKind::Partial),
+            "final" => Some(SegmentKind::Final),
+            _ => None,
+        }
+    }
+}
+
+/// 服务端输出识别消息的格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// 每行一条 `<partial|final> <segment> <text>`
+    #[default]
+    Text,
+    /// 每行一个 JSON 对象，带有时间信息和会话 ID
+    Json,
+}
+
+impl FromStr for ResultFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(ResultFormat::Text),
+            "json" => Ok(ResultFormat::Json),
+            _ => Err(format!("Unknown result format: {}", s)),
+        }
+    }
+}
+
+impl ResultFormat {
+    /// 读取 `STT_RESULT_FORMAT`，默认输出文本
+    pub fn from_env() -> Result<Self, String> {
+        let mut format = Self::default();
+        crate::sherpa::env_override("STT_RESULT_FORMAT", &mut format)?;
+        Ok(format)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultFormat::Text => "text",
+            ResultFormat::Json => "json",
+        }
+    }
+}
+
+/// 服务端输出的一条识别消息，文本协议下每条消息占一行：`<partial|final> <segment> <text>\n`，
+/// 多声道分别识别时带上声道标签：`<partial|final> <channel>:<segment> <text>\n`。
+///
+/// 同一个 `segment` 可能先有多条 partial，最后以一条 final 结束，final 之后该句不会再变化。
+/// 各声道的句子分别编号。
+///
+/// `start`、`end` 是该句在会话音频中的起止时间，`timestamps` 是各 token 在会话音频中的开始时间，
+/// 单位都是秒，只在 JSON 格式中输出。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranscriptEvent {
+    pub kind: SegmentKind,
+    pub channel: Option<String>,
+    pub segment: usize,
+    pub text: String,
+    pub start: f32,
+    pub end: f32,
+    pub tokens: Vec<String>,
+    pub timestamps: Vec<f32>,
+}
+
+// JSON 格式的一条消息
+#[derive(Serialize, Deserialize)]
+struct JsonEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    session_id: String,
+    channel: Option<String>,
+    segment: usize,
+    text: String,
+    start: f32,
+    end: f32,
+    tokens: Vec<String>,
+    timestamps: Vec<f32>,
+}
+
+// sherpa-onnx websocket 服务返回的消息，时间戳相对于 `start_time`，没有的字段留空
+#[derive(Serialize)]
+struct SherpaEvent<'a> {
+    text: &'a str,
+    tokens: &'a [String],
+    timestamps: Vec<f32>,
+    ys_probs: [f32; 0],
+    lm_probs: [f32; 0],
+    context_scores: [f32; 0],
+    segment: usize,
+    words: [String; 0],
+    start_time: f32,
+    is_final: bool,
+}
+
+impl TranscriptEvent {
+    pub fn is_final(&self) -> bool {
+        self.kind == SegmentKind::Final
+    }
+
+    pub fn to_line(&self) -> String {
+        match &self.channel {
+            Some(channel) => format!("{} {}:{} {}\n", self.kind.as_str(), channel, self.segment, self.text),
+            None => format!("{} {} {}\n", self.kind.as_str(), self.segment, self.text),
+        }
+    }
+
+    /// JSON 格式的一行，带换行
+    pub fn to_json(&self, session_id: &str) -> String {
+        let event = JsonEvent {
+            kind: self.kind.as_str().to_string(),
+            session_id: session_id.to_string(),
+            channel: self.channel.clone(),
+            segment: self.segment,
+            text: self.text.clone(),
+            start: self.start,
+            end: self.end,
+            tokens: self.tokens.clone(),
+            timestamps: self.timestamps.clone(),
+        };
+        // 只有字符串和数字字段，序列化不会失败
+        format!("{}\n", serde_json::to_string(&event).unwrap_or_default())
+    }
+
+    /// sherpa-onnx websocket 服务格式的 JSON，不带换行
+    pub fn to_sherpa_json(&self) -> String {
+        let event = SherpaEvent {
+            text: &self.text,
+            tokens: &self.tokens,
+            timestamps: self.timestamps.iter().map(|timestamp| timestamp - self.start).collect(),
+            ys_probs: [],
+            lm_probs: [],
+            context_scores: [],
+            segment: self.segment,
+            words: [],
+            start_time: self.start,
+            is_final: self.is_final(),
+        };
+        serde_json::to_string(&event).unwrap_or_default()
+    }
+
+    pub fn to_format(&self, format: ResultFormat, session_id: &str) -> String {
+        match format {
+            ResultFormat::Text => self.to_line(),
+            ResultFormat::Json => self.to_json(session_id),
+        }
+    }
+
+    /// 解析一行消息，文本和 JSON 格式都可以
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.starts_with('{') {
+            return Self::parse_json(line);
+        }
+        let mut parts = line.splitn(3, ' ');
+        let kind = SegmentKind::parse(parts.next()?)?;
+        let field = parts.next()?;
+        let (channel, segment) = match field.rsplit_once(':') {
+            Some((channel, segment)) => (Some(channel.to_string()), segment),
+            None => (None, field),
+        };
+        let segment = segment.parse().ok()?;
+        let text = parts.next().unwrap_or("").to_string();
+        Some(Self { kind, channel, segment, text, ..Self::default() })
+    }
+
+    fn parse_json(line: &str) -> Option<Self> {
+        let event: JsonEvent = serde_json::from_str(line).ok()?;
+        Some(Self {
+            kind: SegmentKind::parse(&event.kind)?,
+            channel: event.channel,
+            segment: event.segment,
+            text: event.text,
+            start: event.start,
+            end: event.end,
+            tokens: event.tokens,
+            timestamps: event.timestamps,
+        })
+    }
+}
+
+/// 把识别流的逐块结果整理成按句编号的 partial/final 消息。
+///
+/// 未变化的 partial 和空文本的句子不会输出，句子编号只在输出 final 后递增，所以是连续的。
+/// 多声道分别识别时每个声道一个 `Segmenter`，输出的消息带上该声道的标签。
+///
+/// 每次送去识别的采样数通过 `advance` 累计，用来计算句子在会话音频中的起止时间。
+#[derive(Debug, Default)]
+pub struct Segmenter {
+    channel: Option<String>,
+    segment: usize,
+    last_partial: String,
+    // 已送去识别的音频时长和当前句的开始时间，单位为秒
+    offset: f64,
+    segment_start: f64,
+}
+
+impl Segmenter {
+    pub fn with_channel(channel: Option<String>) -> Self {
+        Self { channel, ..Self::default() }
+    }
+
+    /// 记录又有 `samples` 个 `sample_rate` 采样率的采样送去了识别
+    pub fn advance(&mut self, samples: usize, sample_rate: u32) {
+        self.offset += samples as f64 / sample_rate as f64;
+    }
+
+    pub fn push(&mut self, result: &RecognitionResult) -> Option<TranscriptEvent> {
+        if result.is_final {
+            self.last_partial.clear();
+            if result.text.is_empty() {
+                // 没有文本的句子不输出，但时间照样往后推
+                self.segment_start = self.offset;
+                return None;
+            }
+            let event = self.event(SegmentKind::Final, result);
+            self.segment += 1;
+            self.segment_start = self.offset;
+            Some(event)
+        } else {
+            if result.text.is_empty() || result.text == self.last_partial {
+                return None;
+            }
+            self.last_partial = result.text.clone();
+            Some(self.event(SegmentKind::Partial, result))
+        }
+    }
+
+    // 识别结果中的 token 时间相对于句子开始，换算成会话音频中的时间
+    fn event(&self, kind: SegmentKind, result: &RecognitionResult) -> TranscriptEvent {
+        TranscriptEvent {
+            kind,
+            channel: self.channel.clone(),
+            segment: self.segment,
+            text: result.text.clone(),
+            start: self.segment_start as f32,
+            end: self.offset as f32,
+            tokens: result.tokens.clone(),
+            timestamps: result.timestamps.iter().map(|timestamp| (self.segment_start + *timestamp as f64) as f32).collect(),
+        }
+    }
+}
+
+/// 客户端用来汇总服务端消息，得到目前为止的完整识别文本：已结束的句子加上当前句的 partial。
+///
+/// 带声道标签的消息按声道分别汇总。
+#[derive(Debug, Default)]
+pub struct TranscriptCollector {
+    channels: Vec<(Option<String>, ChannelTranscript)>,
+}
+
+#[derive(Debug, Default)]
+struct ChannelTranscript {
+    finals: Vec<String>,
+    partial: String,
+}
+
+impl ChannelTranscript {
+    fn text(&self) -> String {
+        self.finals.iter()
+            .chain(std::iter::once(&self.partial))
+            .filter(|text| !text.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl TranscriptCollector {
+    pub fn push(&mut self, event: TranscriptEvent) {
+        let index = match self.channels.iter().position(|(channel, _)| *channel == event.channel) {
+            Some(index) => index,
+            None => {
+                self.channels.push((event.channel.clone(), ChannelTranscript::default()));
+                self.channels.len() - 1
+            },
+        };
+        let transcript = &mut self.channels[index].1;
+        if event.is_final() {
+            transcript.partial.clear();
+            if transcript.finals.len() <= event.segment {
+                transcript.finals.resize(event.segment + 1, String::new());
+            }
+            transcript.finals[event.segment] = event.text;
+        } else {
+            transcript.partial = event.text;
+        }
+    }
+
+    pub fn has_final(&self) -> bool {
+        self.channels.iter().any(|(_, transcript)| !transcript.finals.is_empty())
+    }
+
+    /// 单声道时是完整的文本，多声道时每个声道一行：`<channel>: <text>`
+    pub fn text(&self) -> String {
+        self.channels.iter()
+            .map(|(channel, transcript)| match channel {
+                Some(channel) => format!("{}: {}", channel, transcript.text()),
+                None => transcript.text(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(text: &str, is_final: bool) -> RecognitionResult {
+        RecognitionResult { text: text.to_string(), is_final, ..Default::default() }
+    }
+
+    #[test]
+    fn push_numbers_segments_and_skips_repeated_partials() {
+        let mut segmenter = Segmenter::default();
+        let kinds = [("a", false), ("a", false), ("ab", false), ("ab", true), ("c", false), ("cd", true)]
+            .into_iter()
+            .filter_map(|(text, is_final)| segmenter.push(&result(text, is_final)))
+            .map(|event| (event.kind, event.segment, event.text))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            (SegmentKind::Partial, 0, "a".to_string()),
+            (SegmentKind::Partial, 0, "ab".to_string()),
+            (SegmentKind::Final, 0, "ab".to_string()),
+            (SegmentKind::Partial, 1, "c".to_string()),
+            (SegmentKind::Final, 1, "cd".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn empty_final_is_skipped_without_using_a_segment_number() {
+        let mut segmenter = Segmenter::default();
+        segmenter.advance(16000, 16000);
+        assert_eq!(segmenter.push(&result("", true)), None);
+        segmenter.advance(8000, 16000);
+        let event = segmenter.push(&result("hello", true)).unwrap();
+        assert_eq!(event.segment, 0);
+        // 空句的时间仍然计入，下一句从它结束的地方开始
+        assert_eq!((event.start, event.end), (1.0, 1.5));
+        assert_eq!(segmenter.push(&result("", false)), None);
+    }
+
+    #[test]
+    fn line_round_trip_keeps_channel_label() {
+        let events = [
+            TranscriptEvent { kind: SegmentKind::Partial, segment: 3, text: "hello world".to_string(), ..Default::default() },
+            TranscriptEvent { kind: SegmentKind::Final, channel: Some("agent".to_string()), segment: 0, text: "你好".to_string(), ..Default::default() },
+            TranscriptEvent { kind: SegmentKind::Final, channel: Some("1".to_string()), segment: 2, text: String::new(), ..Default::default() },
+        ];
+        for event in events {
+            let line = event.to_line();
+            assert_eq!(TranscriptEvent::parse_line(&line).as_ref(), Some(&event), "line: {:?}", line);
+        }
+        assert_eq!(TranscriptEvent::parse_line("final 0:x text"), None);
+        assert_eq!(TranscriptEvent::parse_line("done 0 text"), None);
+    }
+
+    #[test]
+    fn json_lines_have_a_fixed_format() {
+        let partial = TranscriptEvent {
+            kind: SegmentKind::Partial,
+            segment: 1,
+            text: "hello".to_string(),
+            start: 1.5,
+            end: 2.0,
+            tokens: vec!["he".to_string(), "llo".to_string()],
+            timestamps: vec![1.5, 1.75],
+            ..Default::default()
+        };
+        assert_eq!(partial.to_json("s1"), concat!(
+            r#"{"type":"partial","session_id":"s1","channel":null,"segment":1,"text":"hello","start":1.5,"end":2.0,"#,
+            r#""tokens":["he","llo"],"timestamps":[1.5,1.75]}"#, "\n",
+        ));
+        let last = TranscriptEvent { kind: SegmentKind::Final, channel: Some("agent".to_string()), text: "你好 \"world\"".to_string(), ..partial.clone() };
+        assert_eq!(last.to_json("s1"), concat!(
+            r#"{"type":"final","session_id":"s1","channel":"agent","segment":1,"text":"你好 \"world\"","start":1.5,"end":2.0,"#,
+            r#""tokens":["he","llo"],"timestamps":[1.5,1.75]}"#, "\n",
+        ));
+        for event in [partial, last] {
+            assert_eq!(TranscriptEvent::parse_line(&event.to_json("s1")), Some(event));
+        }
+    }
+}
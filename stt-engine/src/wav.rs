@@ -0,0 +1,327 @@
+use std::str::FromStr;
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub const WAVE_FORMAT_MULAW: u16 = 0x0007;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// 流式解析时，data 之前的数据块最多缓存这么多字节，防止异常数据占满内存
+const MAX_HEADER_SIZE: usize = 1 << 20;
+
+/// 服务端收到的音频数据是否带有容器头
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AudioContainer {
+    /// 裸 PCM 数据
+    #[default]
+    Raw,
+    /// 带 RIFF 头的 WAV 数据，服务端先解析并校验头部，只把 data 块送去识别
+    Wav,
+    /// 完整的 FLAC、MP3 或 Ogg 文件，服务端收完整个文件后再解码识别
+    Compressed,
+}
+
+impl FromStr for AudioContainer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "raw" => Ok(AudioContainer::Raw),
+            "wav" => Ok(AudioContainer::Wav),
+            "compressed" => Ok(AudioContainer::Compressed),
+            _ => Err(format!("Unknown audio container: {}", s)),
+        }
+    }
+}
+
+impl AudioContainer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioContainer::Raw => "raw",
+            AudioContainer::Wav => "wav",
+            AudioContainer::Compressed => "compressed",
+        }
+    }
+}
+
+/// fmt 块中的音频格式，`format_tag` 已经把 WAVE_FORMAT_EXTENSIBLE 展开成实际的子格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub block_align: u16,
+}
+
+impl WavFormat {
+    fn parse(chunk: &[u8]) -> Result<Self, String> {
+        if chunk.len() < 16 {
+            return Err(format!("fmt chunk too short: {} bytes", chunk.len()));
+        }
+        let mut format_tag = read_u16(chunk, 0);
+        if format_tag == WAVE_FORMAT_EXTENSIBLE {
+            // cbSize(2) + wValidBitsPerSample(2) + dwChannelMask(4) + SubFormat GUID(16)
+            if chunk.len() < 40 {
+                return Err("fmt chunk too short for WAVE_FORMAT_EXTENSIBLE".to_string());
+            }
+            format_tag = read_u16(chunk, 24);
+        }
+        let format = Self {
+            format_tag,
+            channels: read_u16(chunk, 2),
+            sample_rate: read_u32(chunk, 4),
+            block_align: read_u16(chunk, 12),
+            bits_per_sample: read_u16(chunk, 14),
+        };
+        format.validate()?;
+        Ok(format)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let bits_supported = match self.format_tag {
+            WAVE_FORMAT_PCM => matches!(self.bits_per_sample, 8 | 16 | 24 | 32),
+            WAVE_FORMAT_IEEE_FLOAT => self.bits_per_sample == 32,
+            WAVE_FORMAT_ALAW | WAVE_FORMAT_MULAW => self.bits_per_sample == 8,
+            tag => return Err(format!("Unsupported wav format tag: {:#06x}", tag)),
+        };
+        if !bits_supported {
+            return Err(format!("Unsupported bits per sample: {}", self.bits_per_sample));
+        }
+        if self.channels == 0 {
+            return Err("Invalid channel count: 0".to_string());
+        }
+        if self.sample_rate == 0 {
+            return Err("Invalid sample rate: 0".to_string());
+        }
+        let expected_align = self.channels as u32 * (self.bits_per_sample as u32 / 8);
+        if self.block_align as u32 != expected_align {
+            return Err(format!("Invalid block align: {}, expected {}", self.block_align, expected_align));
+        }
+        Ok(())
+    }
+}
+
+/// 完整 WAV 文件的解析结果，`data` 是 data 块中的音频数据
+#[derive(Debug)]
+pub struct WavFile<'a> {
+    pub format: WavFormat,
+    pub data: &'a [u8],
+}
+
+/// 解析完整的 WAV 文件，data 块之后的其他块会被忽略。
+///
+/// data 块长度为 0 或 0xFFFFFFFF（边录边写的文件）以及文件被截断时，取到文件末尾为止。
+pub fn parse(bytes: &[u8]) -> Result<WavFile<'_>, String> {
+    let header = scan_header(bytes)?.ok_or("Incomplete wav header".to_string())?;
+    let end = match header.data_len {
+        Some(len) => (header.data_offset + len).min(bytes.len()),
+        None => bytes.len(),
+    };
+    Ok(WavFile {
+        format: header.format,
+        data: &bytes[header.data_offset..end],
+    })
+}
+
+struct WavHeader {
+    format: WavFormat,
+    data_offset: usize,
+    data_len: Option<usize>,
+}
+
+/// 扫描 RIFF 头直到 data 块开始，数据不够时返回 `Ok(None)`
+fn scan_header(bytes: &[u8]) -> Result<Option<WavHeader>, String> {
+    if bytes.len() < 12 {
+        return Ok(None);
+    }
+    if &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Not a RIFF/WAVE file".to_string());
+    }
+    let mut format = None;
+    let mut offset = 12;
+    loop {
+        if bytes.len() < offset + 8 {
+            return Ok(None);
+        }
+        let id = &bytes[offset..offset + 4];
+        let size = read_u32(bytes, offset + 4) as usize;
+        let body = offset + 8;
+        if id == b"data" {
+            let format = format.ok_or("data chunk found before fmt chunk".to_string())?;
+            let data_len = if size == 0 || size == u32::MAX as usize { None } else { Some(size) };
+            return Ok(Some(WavHeader { format, data_offset: body, data_len }));
+        }
+        // 其他块（LIST 等）需要完整读到后才能找到下一个块，奇数长度的块后面有一个填充字节
+        let next = body + size + (size & 1);
+        if id == b"fmt " {
+            if bytes.len() < body + size {
+                return Ok(None);
+            }
+            format.replace(WavFormat::parse(&bytes[body..body + size])?);
+        }
+        if bytes.len() < next {
+            return Ok(None);
+        }
+        offset = next;
+    }
+}
+
+#[derive(Debug, Default)]
+enum StreamState {
+    #[default]
+    Header,
+    Data {
+        remaining: Option<usize>,
+    },
+    Trailer,
+}
+
+/// 流式 WAV 解析器，用于边接收边解析。
+///
+/// 头部没有收完时缓存数据，收完后只把 data 块中的字节交给调用方，data 块之后的内容被丢弃。
+#[derive(Debug, Default)]
+pub struct WavStreamParser {
+    buffer: Vec<u8>,
+    format: Option<WavFormat>,
+    state: StreamState,
+}
+
+impl WavStreamParser {
+    pub fn format(&self) -> Option<WavFormat> {
+        self.format
+    }
+
+    /// 送入新收到的字节，返回其中属于 data 块的部分，头部不完整时返回空
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        match &mut self.state {
+            StreamState::Header => {
+                self.buffer.extend_from_slice(bytes);
+                match scan_header(&self.buffer)? {
+                    Some(header) => {
+                        self.format.replace(header.format);
+                        self.state = StreamState::Data { remaining: header.data_len };
+                        let buffer = std::mem::take(&mut self.buffer);
+                        self.push(&buffer[header.data_offset..])
+                    },
+                    None if self.buffer.len() > MAX_HEADER_SIZE => {
+                        Err(format!("Wav header exceeds {} bytes", MAX_HEADER_SIZE))
+                    },
+                    None => Ok(Vec::new()),
+                }
+            },
+            StreamState::Data { remaining: None } => Ok(bytes.to_vec()),
+            StreamState::Data { remaining: Some(remaining) } => {
+                let n = bytes.len().min(*remaining);
+                *remaining -= n;
+                if *remaining == 0 {
+                    self.state = StreamState::Trailer;
+                }
+                Ok(bytes[..n].to_vec())
+            },
+            StreamState::Trailer => Ok(Vec::new()),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn fmt(format_tag: u16, channels: u16, sample_rate: u32, bits_per_sample: u16) -> Vec<u8> {
+        let block_align = channels * bits_per_sample / 8;
+        let mut body = Vec::new();
+        body.extend_from_slice(&format_tag.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits_per_sample.to_le_bytes());
+        chunk(b"fmt ", &body)
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    fn stream(bytes: &[u8], step: usize) -> Result<Vec<u8>, String> {
+        let mut parser = WavStreamParser::default();
+        let mut data = Vec::new();
+        for part in bytes.chunks(step) {
+            data.extend(parser.push(part)?);
+        }
+        Ok(data)
+    }
+
+    #[test]
+    fn skips_odd_sized_and_list_chunks_before_data() {
+        let data = (0..64).collect::<Vec<u8>>();
+        let bytes = wav(&[
+            chunk(b"junk", &[1, 2, 3]),
+            fmt(WAVE_FORMAT_PCM, 1, 16000, 16),
+            chunk(b"LIST", b"INFOISFT\x05\0\0\0abcd\0"),
+            chunk(b"data", &data),
+            chunk(b"id3 ", &[9; 7]),
+        ]);
+        let file = parse(&bytes).unwrap();
+        assert_eq!(file.format, WavFormat { format_tag: WAVE_FORMAT_PCM, channels: 1, sample_rate: 16000, bits_per_sample: 16, block_align: 2 });
+        assert_eq!(file.data, data);
+        for step in [1, 3, 64, bytes.len()] {
+            assert_eq!(stream(&bytes, step).unwrap(), data, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn truncated_header_is_incomplete() {
+        let bytes = wav(&[fmt(WAVE_FORMAT_PCM, 2, 8000, 16), chunk(b"LIST", &[0; 5]), chunk(b"data", &[7; 8])]);
+        let data_offset = bytes.len() - 8;
+        for len in 0..data_offset {
+            assert_eq!(parse(&bytes[..len]).unwrap_err(), "Incomplete wav header", "len {}", len);
+            assert_eq!(stream(&bytes[..len], 1).unwrap(), Vec::<u8>::new(), "len {}", len);
+        }
+        // data 块被截断时取到文件末尾
+        assert_eq!(parse(&bytes[..data_offset + 3]).unwrap().data, [7; 3]);
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        for bytes in [
+            wav(&[fmt(WAVE_FORMAT_IEEE_FLOAT, 1, 16000, 64), chunk(b"data", &[0; 8])]),
+            wav(&[fmt(WAVE_FORMAT_PCM, 1, 16000, 12), chunk(b"data", &[0; 8])]),
+            wav(&[fmt(WAVE_FORMAT_PCM, 0, 16000, 16), chunk(b"data", &[0; 8])]),
+            wav(&[chunk(b"data", &[0; 8]), fmt(WAVE_FORMAT_PCM, 1, 16000, 16)]),
+        ] {
+            assert!(parse(&bytes).is_err());
+            assert!(stream(&bytes, 5).is_err());
+        }
+        assert!(parse(b"RIFX\0\0\0\0WAVE").is_err());
+        assert!(parse(&wav(&[fmt(WAVE_FORMAT_IEEE_FLOAT, 1, 16000, 32), chunk(b"data", &[0; 8])])).is_ok());
+    }
+}
@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use stt_engine::{endpoint::{server::{Server, ServerConfig}, Endpoint}, protocol::{read_frame, start_session, write_frame, Frame, FrameDecoder, HandshakeError, ServerError, StartConfig}, sherpa::MockRecognizer, transcript::TranscriptEvent};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// 已启动的服务端的各个监听地址
+pub struct Started {
+    pub addr: SocketAddr,
+    pub websocket: Option<SocketAddr>,
+    pub http: Option<SocketAddr>,
+    pub grpc: Option<SocketAddr>,
+    pub recognizer: Arc<MockRecognizer>,
+}
+
+/// 在随机端口上启动使用模拟识别器的服务端，设置了的其他监听也使用随机端口
+pub async fn launch(config: ServerConfig) -> Started {
+    let port = |port: Option<u16>| port.map(|_| 0);
+    let config = ServerConfig {
+        ip: "127.0.0.1",
+        port: 0,
+        websocket_port: port(config.websocket_port),
+        http_port: port(config.http_port),
+        grpc_port: port(config.grpc_port),
+        ..config
+    };
+    let server = Server::<MockRecognizer>::init(config).await.expect("server failed to start");
+    let started = Started {
+        addr: server.local_addr().expect("server is not listening"),
+        websocket: server.websocket_addr(),
+        http: server.http_addr(),
+        grpc: server.grpc_addr(),
+        recognizer: server.recognizer(),
+    };
+    tokio::spawn(async move { server.run().await });
+    started
+}
+
+/// 在随机端口上启动使用模拟识别器的服务端，返回 TCP 监听地址和识别器
+pub async fn start(config: ServerConfig) -> (SocketAddr, Arc<MockRecognizer>) {
+    let started = launch(config).await;
+    (started.addr, started.recognizer)
+}
+
+/// `seconds` 秒 16kHz 单声道 16 位的 PCM
+pub fn pcm(seconds: f32) -> Vec<u8> {
+    let samples = (16000.0 * seconds) as usize;
+    (0..samples).flat_map(|i| (((i % 100) as i16 - 50) * 100).to_le_bytes()).collect()
+}
+
+/// 逐行解析服务端返回的识别消息
+pub fn parse_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<TranscriptEvent> {
+    lines.into_iter()
+        .map(|line| TranscriptEvent::parse_line(line).unwrap_or_else(|| panic!("invalid line: {:?}", line)))
+        .collect()
+}
+
+/// 用分帧协议发送一段音频，返回会话 ID 和收到的全部识别消息
+pub async fn stream_framed<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, start: &StartConfig, audio: &[u8])
+    -> Result<(String, Vec<String>), HandshakeError> {
+    let mut decoder = FrameDecoder::default();
+    let (_, session_id) = start_session(stream, &mut decoder, start).await?;
+    for chunk in audio.chunks(6400) {
+        write_frame(stream, &Frame::Audio(chunk.to_vec())).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    write_frame(stream, &Frame::End).await.unwrap();
+    let mut results = Vec::new();
+    loop {
+        match read_frame(stream, &mut decoder).await.unwrap() {
+            Some(Frame::Result(line)) => results.push(line),
+            Some(Frame::End) => return Ok((session_id, results)),
+            Some(Frame::Error(error)) => return Err(HandshakeError::Rejected(error)),
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+}
+
+/// 握手被拒绝时的错误
+pub fn rejected(result: Result<(String, Vec<String>), HandshakeError>) -> ServerError {
+    match result {
+        Err(HandshakeError::Rejected(error)) => error,
+        other => panic!("expected rejection, got {:?}", other),
+    }
+}
+
+/// 检查每句都以 final 结束、句子编号连续，返回各句 final 的文本
+pub fn final_texts(events: &[TranscriptEvent]) -> Vec<String> {
+    assert!(events.last().is_some_and(TranscriptEvent::is_final), "last event is not final: {:?}", events.last());
+    let mut segment = 0;
+    let mut finals = Vec::new();
+    for event in events {
+        assert_eq!(event.segment, segment, "unexpected segment in {:?}", event);
+        if event.is_final() {
+            finals.push(event.text.clone());
+            segment += 1;
+        }
+    }
+    finals
+}
+
+/// 检查模拟识别器的各句 final：除最后一句外都达到了 `segment_samples`，合起来正好是送入的全部采样
+pub fn assert_mock_finals(finals: &[String], segment_samples: usize, total_samples: usize) {
+    let counts = finals.iter()
+        .map(|text| text.strip_prefix("mock ").and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or_else(|| panic!("unexpected text: {:?}", text)))
+        .collect::<Vec<_>>();
+    assert_eq!(counts.iter().sum::<usize>(), total_samples, "finals: {:?}", finals);
+    assert!(counts[..counts.len() - 1].iter().all(|count| *count >= segment_samples), "finals: {:?}", finals);
+}
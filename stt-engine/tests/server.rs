@@ -0,0 +1,155 @@
+mod common;
+
+use std::time::Duration;
+
+use stt_engine::{client::{self, RunningResult, Transport}, endpoint::server::{ServerConfig, UnixSocketConfig}, grpc::proto::{speech_recognizer_client::SpeechRecognizerClient, RecognitionConfig, RecognizeRequest}, protocol::{start_session, ErrorCode, FrameDecoder, ProtocolMode, StartConfig}, transcript::SegmentKind};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpStream, UnixStream}};
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+async fn stream_raw(addr: std::net::SocketAddr, audio: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    for chunk in audio.chunks(6400) {
+        stream.write_all(chunk).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    stream.shutdown().await.unwrap();
+    let mut output = String::new();
+    stream.read_to_string(&mut output).await.unwrap();
+    output
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn raw_protocol_streams_partial_and_final_lines() {
+    let (addr, _) = common::start(ServerConfig { channel_num: 2, protocol: ProtocolMode::Raw, ..Default::default() }).await;
+    let output = stream_raw(addr, &common::pcm(2.5)).await;
+    let events = common::parse_lines(output.lines());
+    // 模拟识别器每 16000 个采样结束一句，剩下的半句在客户端关闭写端后输出
+    let finals = common::final_texts(&events);
+    assert_eq!(finals.len(), 3);
+    common::assert_mock_finals(&finals, 16000, 40000);
+    assert_eq!(events[0].kind, SegmentKind::Partial);
+    assert!(events.iter().filter(|event| !event.is_final()).count() >= 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn framed_protocol_streams_results_with_a_fresh_stream_per_session() {
+    // 只有一个通道，后面的会话排队等前一个会话释放通道
+    let (addr, recognizer) = common::start(ServerConfig { channel_num: 1, max_wait: 5, ..Default::default() }).await;
+    let mut session_ids = Vec::new();
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (session_id, results) = common::stream_framed(&mut stream, &StartConfig::default(), &common::pcm(1.5)).await.unwrap();
+        let events = common::parse_lines(results.iter().map(String::as_str));
+        common::assert_mock_finals(&common::final_texts(&events), 16000, 24000);
+        session_ids.push(session_id);
+    }
+    session_ids.sort();
+    session_ids.dedup();
+    assert_eq!(session_ids.len(), 3);
+    // 识别流结束输入后不能再用，通道打开时创建一个，每个会话结束后再新建一个
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while recognizer.stream_count() < 4 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(recognizer.stream_count(), 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_when_all_channels_are_in_use() {
+    let (addr, _) = common::start(ServerConfig { channel_num: 1, read_timeout: 5, ..Default::default() }).await;
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    let mut decoder = FrameDecoder::default();
+    start_session(&mut first, &mut decoder, &StartConfig::default()).await.unwrap();
+
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    let error = common::rejected(common::stream_framed(&mut second, &StartConfig::default(), &common::pcm(0.5)).await);
+    assert_eq!(error.code, ErrorCode::Busy);
+    let record = client::run_with("127.0.0.1".to_string(), addr.port(), None, None, "data/segment/split_part_28.wav".to_string(),
+        ProtocolMode::Framed, false).await.unwrap();
+    assert_eq!(record.running_result(), RunningResult::ServerError(ErrorCode::Busy));
+    assert_eq!(record.server_error(), Some(ErrorCode::Busy));
+
+    // 第一个会话结束后通道回到池中
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut third = TcpStream::connect(addr).await.unwrap();
+    let (_, results) = common::stream_framed(&mut third, &StartConfig::default(), &common::pcm(0.5)).await.unwrap();
+    assert_eq!(common::final_texts(&common::parse_lines(results.iter().map(String::as_str))), ["mock 8000"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grpc_recognize_returns_for_long_uploads() {
+    let started = common::launch(ServerConfig { channel_num: 1, grpc_port: Some(0), ..Default::default() }).await;
+    let mut client = SpeechRecognizerClient::connect(format!("http://{}", started.grpc.unwrap())).await.unwrap();
+    // 70 秒音频分成 70 块，中间结果远多于响应通道的容量
+    let audio = common::pcm(70.0);
+    let request = RecognizeRequest {
+        config: Some(RecognitionConfig { container: "raw".to_string(), ..Default::default() }),
+        audio,
+    };
+    let response = tokio::time::timeout(Duration::from_secs(30), client.recognize(request)).await
+        .expect("recognize did not return").unwrap().into_inner();
+    let finals = response.segments.iter().map(|segment| segment.text.clone()).collect::<Vec<_>>();
+    common::assert_mock_finals(&finals, 16000, 70 * 16000);
+
+    // 通道已经释放，下一个请求不会繁忙
+    let request = RecognizeRequest { config: None, audio: common::pcm(0.5) };
+    assert_eq!(client.recognize(request).await.unwrap().into_inner().text, "mock 8000");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn websocket_returns_sherpa_messages_and_closes_with_done() {
+    let started = common::launch(ServerConfig { channel_num: 1, websocket_port: Some(0), ..Default::default() }).await;
+    let (mut websocket, _) = tokio_tungstenite::connect_async(format!("ws://{}", started.websocket.unwrap())).await.unwrap();
+    // 与 sherpa-onnx 的 websocket 服务一样，音频是识别器采样率的 f32 采样，发完后发送文本 "Done"
+    let samples = (0..24000).map(|i| ((i % 100) as f32 - 50.0) / 100.0).collect::<Vec<f32>>();
+    for chunk in samples.chunks(1600) {
+        let data = chunk.iter().flat_map(|sample| sample.to_le_bytes()).collect::<Vec<_>>();
+        websocket.send(Message::binary(data)).await.unwrap();
+    }
+    websocket.send(Message::text("Done")).await.unwrap();
+
+    let mut messages = Vec::new();
+    let close = loop {
+        match websocket.next().await.expect("closed without a close frame").unwrap() {
+            Message::Text(text) => messages.push(serde_json::from_str::<serde_json::Value>(text.as_str()).unwrap()),
+            Message::Close(frame) => break frame.expect("close frame without a reason"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    };
+    assert_eq!(close.code, CloseCode::Normal);
+    assert_eq!(close.reason.as_str(), "Done");
+
+    for message in &messages {
+        for key in ["text", "tokens", "timestamps", "ys_probs", "lm_probs", "context_scores", "segment", "words", "start_time", "is_final"] {
+            assert!(message.get(key).is_some(), "missing {} in {}", key, message);
+        }
+    }
+    let finals = messages.iter()
+        .filter(|message| message["is_final"] == true)
+        .map(|message| (message["segment"].as_u64().unwrap(), message["text"].as_str().unwrap(), message["start_time"].as_f64().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(finals, [(0, "mock 16000", 0.0), (1, "mock 8000", 1.0)]);
+    assert!(messages.iter().any(|message| message["is_final"] == false));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unix_socket_runs_a_client_session() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("stt-engine-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    common::launch(ServerConfig { channel_num: 1, unix_socket: Some(UnixSocketConfig::new(&path)), ..Default::default() }).await;
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+
+    let record = client::run_on(Transport::Unix(path.clone()), None, "data/segment/split_part_28.wav".to_string(), ProtocolMode::Framed, false)
+        .await.unwrap();
+    assert_eq!(record.running_result(), RunningResult::Succeccess);
+
+    // 与 TCP 使用同一个分帧协议
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    let (_, results) = common::stream_framed(&mut stream, &StartConfig::default(), &common::pcm(1.5)).await.unwrap();
+    assert_eq!(common::final_texts(&common::parse_lines(results.iter().map(String::as_str))), ["mock 16000", "mock 8000"]);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+use stt_engine::{endpoint::{server::{Server, ServerConfig}, Endpoint}, protocol::{HandshakeError, StartConfig}, sherpa::MockRecognizer, tls::{ClientTlsConfig, ServerTlsConfig, TlsClient}};
+use tokio::net::TcpStream;
+
+// 一个测试用的 CA 和它签发的服务端、客户端证书，都写成 PEM 文件
+struct Certs {
+    ca: PathBuf,
+    server_cert: PathBuf,
+    server_key: PathBuf,
+    client_cert: PathBuf,
+    client_key: PathBuf,
+}
+
+fn write(dir: &Path, name: &str, pem: String) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, pem).unwrap();
+    path
+}
+
+fn certs(name: &str) -> Certs {
+    let dir = std::env::temp_dir().join(format!("stt-engine-tls-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate().unwrap();
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let issuer = Issuer::new(ca_params, ca_key);
+    let server_key = KeyPair::generate().unwrap();
+    let server_cert = CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()]).unwrap()
+        .signed_by(&server_key, &issuer).unwrap();
+    let client_key = KeyPair::generate().unwrap();
+    let client_cert = CertificateParams::new(vec!["client".to_string()]).unwrap().signed_by(&client_key, &issuer).unwrap();
+    Certs {
+        ca: write(&dir, "ca.pem", ca.pem()),
+        server_cert: write(&dir, "server.pem", server_cert.pem()),
+        server_key: write(&dir, "server.key", server_key.serialize_pem()),
+        client_cert: write(&dir, "client.pem", client_cert.pem()),
+        client_key: write(&dir, "client.key", client_key.serialize_pem()),
+    }
+}
+
+async fn start(tls: ServerTlsConfig) -> u16 {
+    common::start(ServerConfig { channel_num: 2, tls: Some(tls), ..Default::default() }).await.0.port()
+}
+
+// 建立 TLS 连接并发送 1.5 秒音频，握手或会话失败时返回错误
+async fn stream(port: u16, client: &TlsClient) -> Result<Vec<String>, String> {
+    let tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut tls = client.connect("127.0.0.1", tcp).await.map_err(|e| e.to_string())?;
+    common::stream_framed(&mut tls, &StartConfig::default(), &common::pcm(1.5)).await
+        .map(|(_, results)| results)
+        .map_err(|e| e.to_string())
+}
+
+fn assert_transcribed(results: Vec<String>) {
+    let events = common::parse_lines(results.iter().map(String::as_str));
+    common::assert_mock_finals(&common::final_texts(&events), 16000, 24000);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tls_round_trip() {
+    let certs = certs("round-trip");
+    let port = start(ServerTlsConfig::new(&certs.server_cert, &certs.server_key)).await;
+
+    // 默认按连接的地址校验证书
+    let client = ClientTlsConfig::new(&certs.ca).connector().unwrap();
+    assert_transcribed(stream(port, &client).await.unwrap());
+
+    let mut named = ClientTlsConfig::new(&certs.ca);
+    named.server_name = Some("localhost".to_string());
+    assert_transcribed(stream(port, &named.connector().unwrap()).await.unwrap());
+
+    let mut wrong = ClientTlsConfig::new(&certs.ca);
+    wrong.server_name = Some("other.example".to_string());
+    assert!(stream(port, &wrong.connector().unwrap()).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plaintext_client_is_rejected() {
+    let certs = certs("plaintext");
+    let port = start(ServerTlsConfig::new(&certs.server_cert, &certs.server_key)).await;
+    let mut tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let result = common::stream_framed(&mut tcp, &StartConfig::default(), &common::pcm(0.5)).await;
+    assert!(matches!(result, Err(HandshakeError::Failed(_))), "{:?}", result);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mutual_tls_requires_trusted_client_certificate() {
+    let certs = certs("mutual");
+    let untrusted = self::certs("mutual-untrusted");
+    let mut tls = ServerTlsConfig::new(&certs.server_cert, &certs.server_key);
+    tls.client_ca = Some(certs.ca.clone());
+    let port = start(tls).await;
+
+    let without_cert = ClientTlsConfig::new(&certs.ca).connector().unwrap();
+    assert!(stream(port, &without_cert).await.is_err());
+
+    // 证书由另一个 CA 签发
+    let mut other = ClientTlsConfig::new(&certs.ca);
+    other.cert = Some(untrusted.client_cert.clone());
+    other.key = Some(untrusted.client_key.clone());
+    assert!(stream(port, &other.connector().unwrap()).await.is_err());
+
+    let mut trusted = ClientTlsConfig::new(&certs.ca);
+    trusted.cert = Some(certs.client_cert.clone());
+    trusted.key = Some(certs.client_key.clone());
+    assert_transcribed(stream(port, &trusted.connector().unwrap()).await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_key_fails_startup() {
+    let certs = certs("invalid-key");
+    let tls = ServerTlsConfig::new(&certs.server_cert, &certs.server_cert);
+    let config = ServerConfig { ip: "127.0.0.1", port: 0, tls: Some(tls), ..Default::default() };
+    assert!(Server::<MockRecognizer>::init(config).await.is_none());
+}